@@ -0,0 +1,52 @@
+// Compares the size and encode time of each message format, with `cargo bench`
+#[macro_use]
+extern crate criterion;
+extern crate websocket_red_or_black;
+
+use criterion::{black_box, Criterion};
+use websocket_red_or_black::red_or_black::bots::BotStrategy;
+use websocket_red_or_black::red_or_black::messages::SendableMessage;
+use websocket_red_or_black::red_or_black::protocol::{encode, Format};
+use websocket_red_or_black::red_or_black::rules::RedOrBlack;
+use websocket_red_or_black::red_or_black::settings::GameSettings;
+
+const FORMATS: [(&str, Format); 4] = [
+    ("legacy json", Format::Legacy),
+    ("envelope json", Format::Envelope),
+    ("messagepack", Format::MessagePack),
+    ("cbor", Format::Cbor),
+];
+
+// A page of 40 turns of history, the biggest message a player is sent
+fn history_page() -> SendableMessage {
+    let usernames = ["amy", "bob", "cat", "dan"];
+    let usernames = usernames.iter().map(|u| u.to_string()).collect();
+    let mut game = RedOrBlack::with_settings(usernames, GameSettings::default());
+    for _ in 0..60 {
+        let guess = BotStrategy::Random.guess(&game);
+        game.play_turn(&guess);
+    }
+    SendableMessage::HistoryPage {
+        items: game.get_game_history().clone(),
+        has_more: true,
+    }
+}
+
+fn encoding(c: &mut Criterion) {
+    let message = history_page();
+    for &(name, format) in &FORMATS {
+        let size = encode(&message, format, Some("1".to_string())).len();
+        println!("HistoryPage as {}: {} bytes", name, size);
+    }
+
+    let mut group = c.benchmark_group("encode HistoryPage");
+    for &(name, format) in &FORMATS {
+        group.bench_function(name, |b| {
+            b.iter(|| encode(black_box(&message), format, Some("1".to_string())))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encoding);
+criterion_main!(benches);
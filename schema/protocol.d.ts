@@ -0,0 +1,119 @@
+// Generated by `cargo run --bin red_or_black_schema`, don't edit by hand
+
+export type BotStrategy = "AlwaysRed" | "Random" | "Majority" | "MimicLast";
+
+export type Card = { suit: Suit; value: Value };
+
+export type CardColour = "Red" | "Black";
+
+export type CardCounting = "Off" | "Colours" | "Values";
+
+export type ChatMessage = { text: string; timestamp: number; username: string };
+
+export type DeckExhaustedReason = "DeckFinished" | "PenetrationReached" | "DiscardsRecycled" | "GameEnded";
+
+export type Envelope = { payload?: unknown; request_id?: string | null; type: string; version: number };
+
+export type ExportFormat = "JsonLines" | "Csv";
+
+export type GameLength =
+  | "Unlimited" | "OneDeck"
+  | { Turns: number }
+  | { Minutes: number };
+
+export type GameSettings = { card_counting: CardCounting; game_length: GameLength; play_mode?: PlayMode; reshuffle: ReshufflePolicy; team_penalty?: TeamPenalty; teams?: number };
+
+export type HistoryItem = { card: Card; guess: CardColour; outcome: boolean; penalty: number; team?: number | null; timestamp: number; turn_number: number; username: string };
+
+export type LeaderboardEntry = { accuracy: number; best_streak: number; games_played: number; penalty_served: number; username: string };
+
+export type LimitKind = "RateLimit" | "MessageSize" | "Connections";
+
+export type MessageKind = "Login" | "Guess" | "Chat" | "Other";
+
+export type NackReason = "NotLoggedIn" | "NotPlaying" | "NotYourTurn" | "WrongTurn" | "GameOver" | "AlreadyGuessed";
+
+export type PenaltyShare = { seconds: number; username: string };
+
+export type PlayMode =
+  | "Rotation"
+  | { EveryonePlays: number };
+
+export type PlayerInfo = { bot?: boolean; latency_ms?: number | null; team?: number | null; username: string };
+
+export type PlayerStats = { biggest_penalty: number; black_guesses: number; correct: number; correct_percentage: number; longest_streak: number; penalty_served: number; red_guesses: number; turns: number; username: string };
+
+export type ProfileTotals = { accuracy: number; best_streak: number; correct: number; games_played: number; penalty_served: number; turns: number };
+
+export type ReceivableMessage =
+  | "ListReplays" | "RotateInviteCode" | "ListRooms" | "SubscribeLobby" | "UnsubscribeLobby"
+  | { Login: { code?: string | null; player_id?: string | null; player_token?: string | null; room?: string | null; username: string } }
+  | { Guess: { card_colour: CardColour; guess_id?: string | null; turn_number?: number | null } }
+  | { GetLeaderboard: { room?: string | null } }
+  | { GetProfile: { player_id: string } }
+  | { ExportHistory: { format: ExportFormat } }
+  | { Replay: { game_id: string; speed?: number | null } }
+  | { Chat: { text: string } }
+  | { React: { emoji: string; turn_number: number } }
+  | { CreateRoom: { invite_only?: boolean; password?: string | null; private?: boolean; room: string; settings?: GameSettings | null } }
+  | { Spectate: { code?: string | null; room: string } }
+  | { AddBot: { name?: string | null; strategy: BotStrategy } }
+  | { RemoveBot: { username: string } }
+  | { AssignTeam: { team: number; username: string } }
+  | { GetHistory: { before_turn?: number | null; limit?: number | null } };
+
+export type ReshufflePolicy =
+  | "Immediate" | "RecycleDiscards" | "EndGame"
+  | { Penetration: number };
+
+export type RoomState = "Lobby" | "InProgress" | "Finished";
+
+export type RoomSummary = { name: string; password_required: boolean; players: number; settings: GameSettings; spectators: number; state: RoomState };
+
+export type SendableMessage =
+  | { msg: string; msg_type: "Ok" }
+  | { details?: PlayerInfo[]; msg_type: "Players"; players: string[] }
+  | { msg_type: "Turn"; team?: number | null; turn_number?: number; username: string }
+  | { error: string; msg_type: "Error" }
+  | { msg_type: "LoggedIn" }
+  | { msg_type: "PlayerToken"; player_id: string; token: string }
+  | { card: Card; correct: boolean; guess: CardColour; msg_type: "GuessResult"; penalty: number; shares?: PenaltyShare[]; team?: number | null; username: string }
+  | { msg_type: "Penalty"; penalty: number }
+  | { drinking_seconds: number; msg_type: "CorrectGuess"; username: string }
+  | { drinking_seconds: number; msg_type: "WrongGuess"; username: string }
+  | { msg_type: "PlayerHasLeft"; username: string }
+  | { history: (Card | null)[]; msg_type: "RequestHistory" }
+  | { has_more: boolean; items: HistoryItem[]; msg_type: "HistoryPage" }
+  | { msg_type: "RoundStarted"; turn_number: number; waiting_for: string[]; window_ms: number }
+  | { closes_in_ms: number; msg_type: "GuessReceived"; turn_number: number; username: string; waiting_for: string[] }
+  | { card: Card; everyone_right: boolean; guesses: HistoryItem[]; msg_type: "RoundResult"; penalty: number; shares?: PenaltyShare[]; turn_number: number }
+  | { card: Card; guess: CardColour; msg_type: "HistoryAppended"; outcome: boolean; penalty: number; team?: number | null; timestamp: number; turn_number: number; username: string }
+  | { card: Card; msg_type: "CardHistoryShifted" }
+  | { cards_left: number; msg_type: "CardsLeft" }
+  | { blacks_left: number; cards_left: number; msg_type: "DeckStats"; red_probability: number; reds_left: number; values_left?: ValueCount[] | null }
+  | { deck_size: number; msg_type: "DeckExhausted"; reason: DeckExhaustedReason }
+  | { game_id: string; msg_type: "GameOver"; players: PlayerStats[]; teams?: TeamStats[]; turns_played: number }
+  | { msg_type: "TeamScores"; teams: TeamStats[] }
+  | { entries: LeaderboardEntry[]; msg_type: "Leaderboard"; room?: string | null }
+  | { msg_type: "Profile"; player_id: string; rooms: { [key: string]: ProfileTotals }; totals: ProfileTotals; username: string }
+  | { data: string; format: ExportFormat; msg_type: "HistoryExport" }
+  | { game_ids: string[]; msg_type: "Replays" }
+  | { msg_type: "Chat"; text: string; timestamp: number; username: string }
+  | { emoji: string; msg_type: "Reaction"; turn_number: number; username: string }
+  | { messages: ChatMessage[]; msg_type: "ChatHistory" }
+  | { invite_code?: string | null; msg_type: "RoomCreated"; room: string }
+  | { msg_type: "Rooms"; rooms: RoomSummary[] }
+  | { invite_code: string; msg_type: "InviteCode"; room: string }
+  | { guess_id?: string | null; msg_type: "Ack"; turn_number: number }
+  | { error: string; guess_id?: string | null; msg_type: "Nack"; reason: NackReason; turn_number: number }
+  | { error: string; limit: LimitKind; message_kind?: MessageKind | null; msg_type: "LimitExceeded"; retry_after_ms?: number | null };
+
+export type Suit = "Spade" | "Club" | "Heart" | "Diamond";
+
+export type TeamPenalty = "Split" | "Guesser";
+
+export type TeamStats = { correct: number; penalty: number; players: string[]; team: number; turns: number };
+
+export type Value = "Ace" | "Two" | "Three" | "Four" | "Five" | "Six" | "Seven" | "Eight" | "Nine" | "Ten" | "Jack" | "Queen" | "King";
+
+export type ValueCount = { count: number; value: Value };
@@ -0,0 +1,189 @@
+extern crate serde_json;
+extern crate websocket_red_or_black;
+extern crate ws;
+
+use websocket_red_or_black::red_or_black::messages::ReceivableMessage;
+use websocket_red_or_black::red_or_black::protocol::{decode_sendable, Envelope};
+use websocket_red_or_black::red_or_black::terminal::{parse_input, Input, TerminalView, HELP};
+
+use std::env;
+use std::io::{self, BufRead};
+use std::process;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use ws::{CloseCode, Handler, Handshake, Message, Sender};
+
+const USAGE: &str = "Play red or black in the terminal
+
+Usage: red_or_black_client [options] <username> [url]
+
+The url defaults to ws://127.0.0.1:9000
+
+Options:
+  --room <room>  The room to join (default main)
+  --code <code>  The password or invite code for the room
+  --raw          Print the messages from the server as JSON, and send lines starting with {
+                 as they are
+  --help         Show this message";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+struct Client {
+    out: Sender,
+    login: String,
+    raw: bool,
+    view: Arc<Mutex<TerminalView>>,
+}
+
+impl Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> ws::Result<()> {
+        if self.raw {
+            println!("-> {}", self.login);
+        }
+        self.out.send(self.login.as_str())
+    }
+
+    fn on_message(&mut self, msg: Message) -> ws::Result<()> {
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Binary(data) => {
+                println!("<- {} bytes of binary", data.len());
+                return Ok(());
+            }
+        };
+        if self.raw {
+            println!("<- {}", text);
+            return Ok(());
+        }
+        match decode_sendable(&text) {
+            Ok(message) => {
+                for line in self.view.lock().unwrap().render(message) {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => println!("Couldn't read a message from the server: {}", e),
+        }
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        println!("Disconnected ({:?}) {}", code, reason);
+        process::exit(0);
+    }
+
+    // There's only one connection, so there's nothing left to do without it
+    fn on_error(&mut self, err: ws::Error) {
+        eprintln!("Connection error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn envelope(message: &ReceivableMessage) -> String {
+    serde_json::to_string(&Envelope::from_receivable(message, None)).unwrap()
+}
+
+fn send(out: &Sender, json: String, raw: bool) {
+    if raw {
+        println!("-> {}", json);
+    }
+    if let Err(e) = out.send(json) {
+        eprintln!("Failed to send: {}", e);
+    }
+}
+
+fn main() {
+    let mut raw = false;
+    let mut room = None;
+    let mut code = None;
+    let mut positional = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--raw" => raw = true,
+            "--room" => room = Some(args.next().unwrap_or_else(|| fail("--room needs a value"))),
+            "--code" => code = Some(args.next().unwrap_or_else(|| fail("--code needs a value"))),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => fail(&format!("Unknown option {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+    let (username, url) = match positional.len() {
+        1 => (positional.remove(0), "ws://127.0.0.1:9000".to_string()),
+        2 => (positional.remove(0), positional.remove(0)),
+        _ => fail("Give a username, and optionally the url of the server"),
+    };
+
+    let login = envelope(&ReceivableMessage::Login {
+        username: username.clone(),
+        player_id: None,
+        player_token: None,
+        room,
+        code,
+    });
+    let view = Arc::new(Mutex::new(TerminalView::new(&username)));
+
+    // The connection runs on its own thread, and hands back its sender for typed messages
+    let (sender_tx, sender_rx) = mpsc::channel();
+    let connection_view = view.clone();
+    let connection = thread::spawn(move || {
+        let result = ws::connect(url, |out: Sender| {
+            sender_tx.send(out.clone()).unwrap();
+            Client {
+                out,
+                login: login.clone(),
+                raw,
+                view: connection_view.clone(),
+            }
+        });
+        // Closed connections exit the process in on_close, so getting here means it never opened
+        match result {
+            Ok(()) => eprintln!("Couldn't connect"),
+            Err(e) => eprintln!("Couldn't connect: {}", e),
+        }
+        process::exit(1);
+    });
+    let out = match sender_rx.recv() {
+        Ok(out) => out,
+        Err(_) => {
+            connection.join().unwrap();
+            return;
+        }
+    };
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        match parse_input(&line, raw) {
+            Input::Send(message) => send(&out, envelope(&message), raw),
+            Input::SendRaw(json) => send(&out, json, raw),
+            Input::ShowHistory => {
+                for line in view.lock().unwrap().history_lines() {
+                    println!("{}", line);
+                }
+            }
+            Input::ShowPlayers => {
+                for line in view.lock().unwrap().players_lines() {
+                    println!("{}", line);
+                }
+            }
+            Input::Help => println!("{}", HELP),
+            Input::Quit => break,
+            Input::Nothing => {}
+        }
+    }
+    // The process exits once the connection has closed
+    if out.close(CloseCode::Normal).is_ok() {
+        connection.join().unwrap();
+    }
+}
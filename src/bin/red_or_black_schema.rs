@@ -0,0 +1,39 @@
+extern crate websocket_red_or_black;
+
+use websocket_red_or_black::red_or_black::schema::{
+    json_schema, typescript, SCHEMA_FILE, TYPESCRIPT_FILE,
+};
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+// Write the JSON Schema and TypeScript definitions for the protocol into the crate, or with
+// --check, fail if the ones there are out of date
+fn main() {
+    let check = env::args().any(|a| a == "--check");
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut stale = false;
+    for (file, generated) in &[
+        (SCHEMA_FILE, json_schema()),
+        (TYPESCRIPT_FILE, typescript()),
+    ] {
+        let path = root.join(file);
+        let current = fs::read_to_string(&path).unwrap_or_default();
+        if current == *generated {
+            println!("{} is up to date", file);
+        } else if check {
+            eprintln!("{} is out of date", file);
+            stale = true;
+        } else {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, generated).unwrap();
+            println!("Wrote {}", file);
+        }
+    }
+    if stale {
+        eprintln!("Run `cargo run --bin red_or_black_schema` to update them");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,88 @@
+extern crate serde_json;
+extern crate websocket_red_or_black;
+
+use websocket_red_or_black::red_or_black::bots::BotStrategy;
+use websocket_red_or_black::red_or_black::settings::GameSettings;
+use websocket_red_or_black::red_or_black::simulation::{simulate, SimulationSettings};
+
+use std::env;
+use std::fmt::Display;
+use std::process;
+use std::str::FromStr;
+
+const USAGE: &str = "Simulate games of red or black and report how they went
+
+Usage: red_or_black_sim [options]
+
+Options:
+  --games <n>             How many games to play (default 1000)
+  --players <n>           How many players are in each game (default 4)
+  --strategies <list>     Comma separated strategies for the seats, in turn order: always-red,
+                          random, majority or mimic-last. The list is repeated to fill every
+                          seat (default random)
+  --reshuffle <policy>    The same as RED_OR_BLACK_RESHUFFLE (default immediate)
+  --game-length <length>  deck or turns:<n> (default deck)
+  --json                  Print the report as JSON instead of text
+  --help                  Show this message";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse<T>(option: &str, value: Option<String>) -> T
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = value.unwrap_or_else(|| fail(&format!("{} needs a value", option)));
+    value
+        .parse()
+        .unwrap_or_else(|e| fail(&format!("Invalid value for {}: {}", option, e)))
+}
+
+fn main() {
+    let mut games = 1000;
+    let mut players = 4;
+    let mut strategies = vec![BotStrategy::Random];
+    let mut game_settings = GameSettings {
+        game_length: "deck".parse().unwrap(),
+        ..GameSettings::default()
+    };
+    let mut json = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--games" => games = parse(&arg, args.next()),
+            "--players" => players = parse(&arg, args.next()),
+            "--strategies" => {
+                let list: String = parse(&arg, args.next());
+                strategies = list
+                    .split(',')
+                    .map(|s| parse(&arg, Some(s.trim().to_string())))
+                    .collect();
+            }
+            "--reshuffle" => game_settings.reshuffle = parse(&arg, args.next()),
+            "--game-length" => game_settings.game_length = parse(&arg, args.next()),
+            "--json" => json = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => fail(&format!("Unknown option {}", arg)),
+        }
+    }
+
+    let settings = SimulationSettings {
+        games,
+        strategies: strategies.iter().cloned().cycle().take(players).collect(),
+        game_settings,
+    };
+    let report = simulate(&settings).unwrap_or_else(|e| fail(e.description()));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print!("{}", report.to_text());
+    }
+}
@@ -0,0 +1,171 @@
+use red_or_black::access::AccessSettings;
+use red_or_black::chat::ChatSettings;
+use red_or_black::heartbeat::HeartbeatSettings;
+use red_or_black::limits::LimitSettings;
+use red_or_black::settings::GameSettings;
+use red_or_black::sweeper::SweeperSettings;
+use std::env;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub struct Config {
+    pub ip_and_port: String,
+    pub game_settings: GameSettings,
+    // Where to keep player profiles, they are only kept in memory if this isn't set
+    pub profiles_file: Option<PathBuf>,
+    // Where to write the event log of each game so it can be replayed, games aren't recorded if
+    // this isn't set
+    pub replay_dir: Option<PathBuf>,
+    // How long players from a game restored after a restart have to log back in
+    pub reconnect_grace: Duration,
+    pub chat_settings: ChatSettings,
+    // The most rooms that can be open at once, counting `main`. 0 means no limit.
+    pub max_rooms: usize,
+    pub limit_settings: LimitSettings,
+    pub access_settings: AccessSettings,
+    pub sweeper_settings: SweeperSettings,
+    pub heartbeat_settings: HeartbeatSettings,
+    // PEM certificate and private key, connections are encrypted (wss://) if these are set
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+}
+
+// Read an env var and parse it, falling back to the default if it is missing or invalid
+fn parse_env_var<T>(name: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|e| {
+            warn!("Invalid value for {}: {}", name, e);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let address =
+            env::var("RED_OR_BLACK_WEBSERVER_ADDRESS").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let port = env::var("RED_OR_BLACK_WEBSERVER_PORT").unwrap_or_else(|_| "9000".to_string());
+
+        let defaults = GameSettings::default();
+        let game_settings = GameSettings {
+            card_counting: parse_env_var("RED_OR_BLACK_CARD_COUNTING", defaults.card_counting),
+            reshuffle: parse_env_var("RED_OR_BLACK_RESHUFFLE", defaults.reshuffle),
+            game_length: parse_env_var("RED_OR_BLACK_GAME_LENGTH", defaults.game_length),
+            play_mode: parse_env_var("RED_OR_BLACK_PLAY_MODE", defaults.play_mode),
+            teams: parse_env_var("RED_OR_BLACK_TEAMS", defaults.teams),
+            team_penalty: parse_env_var("RED_OR_BLACK_TEAM_PENALTY", defaults.team_penalty),
+        };
+
+        let chat_defaults = ChatSettings::default();
+        let chat_settings = ChatSettings {
+            max_length: parse_env_var("RED_OR_BLACK_CHAT_MAX_LENGTH", chat_defaults.max_length),
+            rate_limit_count: parse_env_var(
+                "RED_OR_BLACK_CHAT_RATE_LIMIT",
+                chat_defaults.rate_limit_count,
+            ),
+            rate_limit_period: Duration::from_secs(parse_env_var(
+                "RED_OR_BLACK_CHAT_RATE_PERIOD_SECS",
+                chat_defaults.rate_limit_period.as_secs(),
+            )),
+            blocked_words: env::var("RED_OR_BLACK_CHAT_BLOCKED_WORDS")
+                .map(|words| {
+                    words
+                        .split(',')
+                        .map(|w| w.trim().to_lowercase())
+                        .filter(|w| !w.is_empty())
+                        .collect()
+                }).unwrap_or(chat_defaults.blocked_words),
+        };
+
+        let limit_defaults = LimitSettings::default();
+        let limit_settings = LimitSettings {
+            max_message_size: parse_env_var(
+                "RED_OR_BLACK_MAX_MESSAGE_SIZE",
+                limit_defaults.max_message_size,
+            ),
+            max_connections_per_ip: parse_env_var(
+                "RED_OR_BLACK_MAX_CONNECTIONS_PER_IP",
+                limit_defaults.max_connections_per_ip,
+            ),
+            max_violations: parse_env_var(
+                "RED_OR_BLACK_MAX_VIOLATIONS",
+                limit_defaults.max_violations,
+            ),
+            login: parse_env_var("RED_OR_BLACK_RATE_LIMIT_LOGIN", limit_defaults.login),
+            guess: parse_env_var("RED_OR_BLACK_RATE_LIMIT_GUESS", limit_defaults.guess),
+            chat: parse_env_var("RED_OR_BLACK_RATE_LIMIT_CHAT", limit_defaults.chat),
+            other: parse_env_var("RED_OR_BLACK_RATE_LIMIT_OTHER", limit_defaults.other),
+        };
+
+        let access_settings = AccessSettings {
+            allowed_origins: env::var("RED_OR_BLACK_ALLOWED_ORIGINS")
+                .map(|origins| {
+                    origins
+                        .split(',')
+                        .map(|o| o.trim().to_string())
+                        .filter(|o| !o.is_empty())
+                        .collect()
+                }).unwrap_or_default(),
+            join_secret: env::var("RED_OR_BLACK_JOIN_SECRET")
+                .ok()
+                .filter(|s| !s.is_empty()),
+        };
+
+        let sweeper_defaults = SweeperSettings::default();
+        let sweeper_settings = SweeperSettings {
+            interval: Duration::from_secs(parse_env_var(
+                "RED_OR_BLACK_SWEEP_INTERVAL_SECS",
+                sweeper_defaults.interval.as_secs(),
+            )),
+            room_idle: Duration::from_secs(parse_env_var(
+                "RED_OR_BLACK_ROOM_IDLE_SECS",
+                sweeper_defaults.room_idle.as_secs(),
+            )),
+            connection_idle: Duration::from_secs(parse_env_var(
+                "RED_OR_BLACK_CONNECTION_IDLE_SECS",
+                sweeper_defaults.connection_idle.as_secs(),
+            )),
+            archive_dir: env::var("RED_OR_BLACK_ARCHIVE_DIR").ok().map(PathBuf::from),
+        };
+
+        let heartbeat_defaults = HeartbeatSettings::default();
+        let heartbeat_settings = HeartbeatSettings {
+            ping_interval: Duration::from_secs(parse_env_var(
+                "RED_OR_BLACK_PING_INTERVAL_SECS",
+                heartbeat_defaults.ping_interval.as_secs(),
+            )),
+            max_missed_pongs: parse_env_var(
+                "RED_OR_BLACK_MAX_MISSED_PONGS",
+                heartbeat_defaults.max_missed_pongs,
+            ),
+        };
+
+        Config {
+            ip_and_port: format!("{}:{}", address, port),
+            game_settings,
+            profiles_file: env::var("RED_OR_BLACK_PROFILES_FILE")
+                .ok()
+                .map(PathBuf::from),
+            replay_dir: env::var("RED_OR_BLACK_REPLAY_DIR").ok().map(PathBuf::from),
+            reconnect_grace: Duration::from_secs(parse_env_var(
+                "RED_OR_BLACK_RECONNECT_GRACE_SECS",
+                120,
+            )),
+            chat_settings,
+            max_rooms: parse_env_var("RED_OR_BLACK_MAX_ROOMS", 100),
+            limit_settings,
+            access_settings,
+            sweeper_settings,
+            heartbeat_settings,
+            tls_cert: env::var("RED_OR_BLACK_TLS_CERT").ok().map(PathBuf::from),
+            tls_key: env::var("RED_OR_BLACK_TLS_KEY").ok().map(PathBuf::from),
+        }
+    }
+}
@@ -1,12 +1,13 @@
 use rand::{thread_rng, Rng};
+use schemars::JsonSchema;
 
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct Card {
     pub value: Value,
     pub suit: Suit,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub enum Value {
     Ace,
     Two,
@@ -23,7 +24,7 @@ pub enum Value {
     King,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub enum Suit {
     Spade,
     Club,
@@ -31,22 +32,64 @@ pub enum Suit {
     Diamond,
 }
 
+impl Suit {
+    pub fn is_red(self) -> bool {
+        self == Suit::Heart || self == Suit::Diamond
+    }
+}
+
+pub const SUITS: [Suit; 4] = [Suit::Spade, Suit::Club, Suit::Diamond, Suit::Heart];
+
+pub const VALUES: [Value; 13] = [
+    Value::Ace,
+    Value::Two,
+    Value::Three,
+    Value::Four,
+    Value::Five,
+    Value::Six,
+    Value::Seven,
+    Value::Eight,
+    Value::Nine,
+    Value::Ten,
+    Value::Jack,
+    Value::Queen,
+    Value::King,
+];
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct ValueCount {
+    pub value: Value,
+    pub count: usize,
+}
+
+// A snapshot of what is left in the deck, used to help players count cards
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct DeckStats {
+    pub cards_left: usize,
+    pub reds_left: usize,
+    pub blacks_left: usize,
+    pub red_probability: f64,
+    pub values_left: Option<Vec<ValueCount>>,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Deck {
     cards: Vec<Card>,
 }
 
+// An unshuffled deck
+impl Default for Deck {
+    fn default() -> Self {
+        Deck::new()
+    }
+}
+
 impl Deck {
     pub fn new() -> Self {
-        use self::Suit::*;
-        use self::Value::*;
-
         let mut cards = Vec::new();
 
-        for suit in &[Spade, Club, Diamond, Heart] {
-            for value in &[
-                Ace, Two, Three, Four, Five, Six, Seven, Eight, Nine, Ten, Jack, Queen, King,
-            ] {
+        for suit in &SUITS {
+            for value in &VALUES {
                 cards.push(Card {
                     value: *value,
                     suit: *suit,
@@ -57,16 +100,75 @@ impl Deck {
     }
 
     pub fn new_shuffled() -> Self {
-        let mut deck = Self::new();
-        thread_rng().shuffle(deck.cards.as_mut_slice());
-        deck
+        Self::shuffled_from(Self::new().cards)
+    }
+
+    // Build a deck out of some already used cards, e.g. a discard pile
+    pub fn shuffled_from(mut cards: Vec<Card>) -> Self {
+        thread_rng().shuffle(cards.as_mut_slice());
+        Deck { cards }
+    }
+
+    // Cards are dealt from the end of the vec
+    pub fn from_cards(cards: Vec<Card>) -> Self {
+        Deck { cards }
     }
 
     pub fn pop(&mut self) -> Option<Card> {
         self.cards.pop()
     }
 
+    // The next card to be dealt
+    pub fn peek(&self) -> Option<Card> {
+        self.cards.last().cloned()
+    }
+
     pub fn len(&self) -> usize {
         self.cards.len()
     }
+
+    pub fn cards(&self) -> &[Card] {
+        &self.cards
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cards.is_empty()
+    }
+
+    pub fn reds_left(&self) -> usize {
+        self.cards.iter().filter(|c| c.suit.is_red()).count()
+    }
+
+    pub fn values_left(&self) -> Vec<ValueCount> {
+        VALUES
+            .iter()
+            .map(|value| ValueCount {
+                value: *value,
+                count: self.cards.iter().filter(|c| c.value == *value).count(),
+            }).collect()
+    }
+
+    // Work out the odds of the next card. If `with_values` is set the count of each value left in
+    // the deck is included as well.
+    pub fn stats(&self, with_values: bool) -> DeckStats {
+        let cards_left = self.len();
+        let reds_left = self.reds_left();
+        let red_probability = if cards_left == 0 {
+            0.0
+        } else {
+            reds_left as f64 / cards_left as f64
+        };
+
+        DeckStats {
+            cards_left,
+            reds_left,
+            blacks_left: cards_left - reds_left,
+            red_probability,
+            values_left: if with_values {
+                Some(self.values_left())
+            } else {
+                None
+            },
+        }
+    }
 }
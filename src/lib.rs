@@ -0,0 +1,17 @@
+extern crate ciborium;
+extern crate rand;
+extern crate rmp_serde;
+extern crate schemars;
+extern crate ws;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "tls")]
+extern crate openssl;
+
+pub mod config;
+pub mod deck;
+pub mod red_or_black;
@@ -1,15 +1,8 @@
-extern crate rand;
-extern crate ws;
-#[macro_use]
-extern crate serde_derive;
 extern crate env_logger;
-extern crate serde;
-extern crate serde_json;
-#[macro_use]
-extern crate log;
+extern crate websocket_red_or_black;
 
-mod deck;
-mod red_or_black;
+use websocket_red_or_black::config::Config;
+use websocket_red_or_black::red_or_black;
 
 use std::env;
 
@@ -19,11 +12,9 @@ fn main() {
         env::set_var("RUST_LOG", "websocket_red_or_black=debug");
     }
 
-    // Read config from env vars
-    let address =
-        env::var("RED_OR_BLACK_WEBSERVER_ADDRESS").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let port = env::var("RED_OR_BLACK_WEBSERVER_PORT").unwrap_or_else(|_| "9000".to_string());
-    let ip_and_port = format!("{}:{}", address, port);
     env_logger::init();
-    red_or_black::start_server(&ip_and_port);
+
+    // Read config from env vars
+    let config = Config::from_env();
+    red_or_black::start_server(config);
 }
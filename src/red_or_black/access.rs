@@ -0,0 +1,196 @@
+use std::fmt;
+
+// Who is allowed to connect to the server
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccessSettings {
+    // Origins that browsers can connect from, e.g. "https://example.com". Anywhere is allowed
+    // if this is empty.
+    pub allowed_origins: Vec<String>,
+    // If set, clients have to pass this as `?secret=...` to connect
+    pub join_secret: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    OriginNotAllowed(String),
+    MissingSecret,
+    WrongSecret,
+}
+
+impl Rejection {
+    pub fn status(&self) -> u16 {
+        match *self {
+            Rejection::OriginNotAllowed(_) => 403,
+            Rejection::MissingSecret | Rejection::WrongSecret => 401,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            Rejection::OriginNotAllowed(_) => "Forbidden",
+            Rejection::MissingSecret | Rejection::WrongSecret => "Unauthorized",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rejection::OriginNotAllowed(ref origin) => {
+                write!(f, "origin {} is not allowed", origin)
+            }
+            Rejection::MissingSecret => write!(f, "no join secret"),
+            Rejection::WrongSecret => write!(f, "wrong join secret"),
+        }
+    }
+}
+
+fn normalise_origin(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_lowercase()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+// Decode a percent encoded query string value
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high * 16 + low);
+                        i += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Get a parameter from the query string of a request path, e.g. "/?secret=abc"
+pub fn query_param(resource: &str, name: &str) -> Option<String> {
+    let (_, query) = resource.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if decode(key) == name => Some(decode(value)),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+// Compare secrets without returning early, so the time taken doesn't give away how much of the
+// secret was right
+pub fn secrets_match(given: &str, secret: &str) -> bool {
+    let given = given.as_bytes();
+    let secret = secret.as_bytes();
+    let mut diff = given.len() ^ secret.len();
+    for (i, b) in secret.iter().enumerate() {
+        diff |= (*b ^ given.get(i).cloned().unwrap_or(0)) as usize;
+    }
+    diff == 0
+}
+
+impl AccessSettings {
+    // Check the Origin header and join secret of a request. Requests without an Origin aren't
+    // from browsers, so they can't be used by other sites and only need the secret.
+    pub fn check(&self, origin: Option<&str>, resource: &str) -> Result<(), Rejection> {
+        if let Some(origin) = origin {
+            let origin = normalise_origin(origin);
+            if !self.allowed_origins.is_empty()
+                && !self
+                    .allowed_origins
+                    .iter()
+                    .any(|o| normalise_origin(o) == origin)
+            {
+                return Err(Rejection::OriginNotAllowed(origin));
+            }
+        }
+
+        if let Some(ref secret) = self.join_secret {
+            match query_param(resource, "secret") {
+                Some(ref given) if secrets_match(given, secret) => {}
+                Some(_) => return Err(Rejection::WrongSecret),
+                None => return Err(Rejection::MissingSecret),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod handshake {
+    use super::*;
+
+    fn settings() -> AccessSettings {
+        AccessSettings {
+            allowed_origins: vec!["https://example.com".to_string()],
+            join_secret: Some("s3cret &co".to_string()),
+        }
+    }
+
+    #[test]
+    fn anything_allowed_by_default() {
+        let open = AccessSettings::default();
+        assert_eq!(open.check(Some("https://evil.com"), "/"), Ok(()));
+        assert_eq!(open.check(None, "/"), Ok(()));
+    }
+
+    #[test]
+    fn origins() {
+        let settings = AccessSettings {
+            join_secret: None,
+            ..settings()
+        };
+        assert_eq!(settings.check(Some("https://EXAMPLE.com/"), "/"), Ok(()));
+        assert_eq!(settings.check(None, "/"), Ok(()));
+        let rejection = settings.check(Some("https://evil.com"), "/").unwrap_err();
+        assert_eq!(
+            rejection,
+            Rejection::OriginNotAllowed("https://evil.com".to_string())
+        );
+        assert_eq!(rejection.status(), 403);
+    }
+
+    #[test]
+    fn join_secret() {
+        let settings = settings();
+        let origin = Some("https://example.com");
+        assert_eq!(
+            settings.check(origin, "/?room=main&secret=s3cret+%26co"),
+            Ok(())
+        );
+        assert_eq!(
+            settings.check(origin, "/?secret=s3cret"),
+            Err(Rejection::WrongSecret)
+        );
+        assert_eq!(settings.check(origin, "/"), Err(Rejection::MissingSecret));
+        assert_eq!(Rejection::MissingSecret.status(), 401);
+    }
+
+    #[test]
+    fn query_params() {
+        assert_eq!(query_param("/?a=1&b=%7e", "b"), Some("~".to_string()));
+        assert_eq!(query_param("/?a=1", "b"), None);
+        assert_eq!(query_param("/", "a"), None);
+        assert_eq!(query_param("/?a=100%", "a"), Some("100%".to_string()));
+    }
+}
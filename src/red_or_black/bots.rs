@@ -0,0 +1,138 @@
+use super::messages::CardColour;
+use super::rules::RedOrBlack;
+use rand::{self, Rng};
+use schemars::JsonSchema;
+use std::str::FromStr;
+
+// The most bots a room can have
+pub const MAX_BOTS: usize = 8;
+
+// How long bots take to guess, in milliseconds, so they don't play faster than a person could
+const MIN_THINK_MS: u64 = 1200;
+const MAX_THINK_MS: u64 = 3500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum BotStrategy {
+    AlwaysRed,
+    Random,
+    // Guess whichever colour has the most cards left in the deck
+    Majority,
+    // Guess the colour of the last card that was turned over
+    MimicLast,
+}
+
+impl BotStrategy {
+    fn name(self) -> &'static str {
+        match self {
+            BotStrategy::AlwaysRed => "Red Bot",
+            BotStrategy::Random => "Random Bot",
+            BotStrategy::Majority => "Counting Bot",
+            BotStrategy::MimicLast => "Copycat Bot",
+        }
+    }
+
+    pub fn guess(self, game: &RedOrBlack) -> CardColour {
+        match self {
+            BotStrategy::AlwaysRed => CardColour::Red,
+            BotStrategy::Random => {
+                if rand::thread_rng().gen() {
+                    CardColour::Red
+                } else {
+                    CardColour::Black
+                }
+            }
+            BotStrategy::Majority => {
+                if game.reds_left() * 2 >= game.cards_left() {
+                    CardColour::Red
+                } else {
+                    CardColour::Black
+                }
+            }
+            BotStrategy::MimicLast => match game.get_card_history().front() {
+                Some(&Some(card)) if !card.suit.is_red() => CardColour::Black,
+                _ => CardColour::Red,
+            },
+        }
+    }
+}
+
+impl FromStr for BotStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "always-red" | "red" => Ok(BotStrategy::AlwaysRed),
+            "random" => Ok(BotStrategy::Random),
+            "majority" => Ok(BotStrategy::Majority),
+            "mimic-last" | "mimic" => Ok(BotStrategy::MimicLast),
+            _ => Err(format!("Unknown bot strategy '{}'", s)),
+        }
+    }
+}
+
+// A name for a new bot that nobody in the game is using, e.g. "Random Bot 2"
+pub fn bot_name(strategy: BotStrategy, taken: &[String]) -> String {
+    let base = strategy.name();
+    let mut name = base.to_string();
+    let mut n = 2;
+    while taken.contains(&name) {
+        name = format!("{} {}", base, n);
+        n += 1;
+    }
+    name
+}
+
+pub fn think_time_ms() -> u64 {
+    rand::thread_rng().gen_range(MIN_THINK_MS, MAX_THINK_MS)
+}
+
+#[cfg(test)]
+mod strategies {
+    use super::*;
+
+    #[test]
+    fn names_are_unique() {
+        let taken = vec!["Red Bot".to_string(), "Red Bot 2".to_string()];
+        assert_eq!(bot_name(BotStrategy::AlwaysRed, &taken), "Red Bot 3");
+        assert_eq!(bot_name(BotStrategy::Random, &taken), "Random Bot");
+    }
+
+    #[test]
+    fn mimics_the_last_card() {
+        let mut game = RedOrBlack::new(vec!["amy".to_string()]);
+        assert_eq!(BotStrategy::MimicLast.guess(&game), CardColour::Red);
+        for _ in 0..5 {
+            game.play_turn(&CardColour::Red);
+            let last = game.get_card_history().front().unwrap().unwrap();
+            let expected = if last.suit.is_red() {
+                CardColour::Red
+            } else {
+                CardColour::Black
+            };
+            assert_eq!(BotStrategy::MimicLast.guess(&game), expected);
+        }
+    }
+
+    #[test]
+    fn guesses_the_majority_colour() {
+        let mut game = RedOrBlack::new(vec!["amy".to_string()]);
+        for _ in 0..10 {
+            let guess = BotStrategy::Majority.guess(&game);
+            let reds = game.reds_left();
+            let blacks = game.cards_left() - reds;
+            match guess {
+                CardColour::Red => assert!(reds >= blacks),
+                CardColour::Black => assert!(blacks > reds),
+            }
+            game.play_turn(&CardColour::Red);
+        }
+    }
+
+    #[test]
+    fn thinks_like_a_person() {
+        for _ in 0..20 {
+            let ms = think_time_ms();
+            assert!((MIN_THINK_MS..MAX_THINK_MS).contains(&ms));
+        }
+    }
+}
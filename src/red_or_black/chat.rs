@@ -0,0 +1,263 @@
+use schemars::JsonSchema;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// How many chat messages are kept to send to players when they join
+const CHAT_HISTORY_SIZE: usize = 20;
+
+// Reactions should be a single emoji, but some emoji are made of a few characters
+const MAX_EMOJI_CHARS: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatSettings {
+    pub max_length: usize,
+    // Each player can send `rate_limit_count` messages (and reactions) every `rate_limit_period`
+    pub rate_limit_count: usize,
+    pub rate_limit_period: Duration,
+    // Words that are starred out of chat messages, in lower case
+    pub blocked_words: Vec<String>,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        ChatSettings {
+            max_length: 280,
+            rate_limit_count: 5,
+            rate_limit_period: Duration::from_secs(10),
+            blocked_words: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct ChatMessage {
+    pub username: String,
+    pub text: String,
+    // Milliseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChatError {
+    Empty,
+    TooLong,
+    RateLimited,
+    InvalidEmoji,
+}
+
+impl ChatError {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            ChatError::Empty => "Message is empty",
+            ChatError::TooLong => "Message is too long",
+            ChatError::RateLimited => "You're sending messages too quickly",
+            ChatError::InvalidEmoji => "Reactions must be a single emoji",
+        }
+    }
+}
+
+pub struct Chat {
+    settings: ChatSettings,
+    history: VecDeque<ChatMessage>,
+    // When each player last sent messages, used for rate limiting
+    sent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Chat {
+    pub fn new(settings: ChatSettings) -> Self {
+        Chat {
+            settings,
+            history: VecDeque::with_capacity(CHAT_HISTORY_SIZE),
+            sent: HashMap::new(),
+        }
+    }
+
+    pub fn get_history(&self) -> &VecDeque<ChatMessage> {
+        &self.history
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.sent.clear();
+    }
+
+    fn check_rate_limit(&mut self, username: &str, now: Instant) -> Result<(), ChatError> {
+        let period = self.settings.rate_limit_period;
+        let sent = self.sent.entry(username.to_string()).or_default();
+        while sent
+            .front()
+            .map(|t| now.duration_since(*t) >= period)
+            .unwrap_or(false)
+        {
+            sent.pop_front();
+        }
+        if sent.len() >= self.settings.rate_limit_count {
+            return Err(ChatError::RateLimited);
+        }
+        sent.push_back(now);
+        Ok(())
+    }
+
+    // Star out any blocked words, ignoring case
+    fn filter(&self, text: &str) -> String {
+        if self.settings.blocked_words.is_empty() {
+            return text.to_string();
+        }
+
+        let mut filtered = String::with_capacity(text.len());
+        let mut word = String::new();
+        for c in text.chars().chain(Some(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            if self.settings.blocked_words.contains(&word.to_lowercase()) {
+                filtered.extend(word.chars().map(|_| '*'));
+            } else {
+                filtered.push_str(&word);
+            }
+            word.clear();
+            filtered.push(c);
+        }
+        // Drop the space added to flush the last word
+        filtered.pop();
+        filtered
+    }
+
+    pub fn post(
+        &mut self,
+        username: &str,
+        text: &str,
+        timestamp: u64,
+        now: Instant,
+    ) -> Result<ChatMessage, ChatError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ChatError::Empty);
+        }
+        if text.chars().count() > self.settings.max_length {
+            return Err(ChatError::TooLong);
+        }
+        self.check_rate_limit(username, now)?;
+
+        let message = ChatMessage {
+            username: username.to_string(),
+            text: self.filter(text),
+            timestamp,
+        };
+        if self.history.len() >= CHAT_HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(message.clone());
+        Ok(message)
+    }
+
+    // Check a reaction is allowed, reactions aren't kept in the history
+    pub fn react(&mut self, username: &str, emoji: &str, now: Instant) -> Result<(), ChatError> {
+        let chars = emoji.chars().count();
+        if chars == 0
+            || chars > MAX_EMOJI_CHARS
+            || emoji
+                .chars()
+                .any(|c| c.is_ascii_alphanumeric() || c.is_whitespace())
+        {
+            return Err(ChatError::InvalidEmoji);
+        }
+        self.check_rate_limit(username, now)
+    }
+}
+
+#[cfg(test)]
+mod moderation {
+    use super::*;
+
+    fn chat() -> Chat {
+        Chat::new(ChatSettings {
+            max_length: 10,
+            rate_limit_count: 2,
+            rate_limit_period: Duration::from_secs(10),
+            blocked_words: vec!["feck".to_string(), "arse".to_string()],
+        })
+    }
+
+    #[test]
+    fn messages_are_kept_in_the_history() {
+        let mut chat = chat();
+        let now = Instant::now();
+        let message = chat.post("mick", " hello ", 1, now).unwrap();
+        assert_eq!(message.text, "hello");
+        assert_eq!(chat.get_history().len(), 1);
+        assert_eq!(chat.get_history()[0], message);
+    }
+
+    #[test]
+    fn history_is_truncated() {
+        let mut chat = Chat::new(ChatSettings {
+            rate_limit_count: 100,
+            ..ChatSettings::default()
+        });
+        let now = Instant::now();
+        for i in 0..CHAT_HISTORY_SIZE + 5 {
+            chat.post("mick", &i.to_string(), 1, now).unwrap();
+        }
+        assert_eq!(chat.get_history().len(), CHAT_HISTORY_SIZE);
+        assert_eq!(chat.get_history()[0].text, "5");
+    }
+
+    #[test]
+    fn length_is_limited() {
+        let mut chat = chat();
+        let now = Instant::now();
+        assert_eq!(chat.post("mick", "   ", 1, now), Err(ChatError::Empty));
+        assert_eq!(
+            chat.post("mick", "this is too long", 1, now),
+            Err(ChatError::TooLong)
+        );
+        assert!(chat.post("mick", "just right", 1, now).is_ok());
+    }
+
+    #[test]
+    fn players_are_rate_limited() {
+        let mut chat = chat();
+        let now = Instant::now();
+        assert!(chat.post("mick", "one", 1, now).is_ok());
+        assert!(chat.react("mick", "🍺", now).is_ok());
+        assert_eq!(
+            chat.post("mick", "three", 1, now),
+            Err(ChatError::RateLimited)
+        );
+        // Other players aren't affected
+        assert!(chat.post("john", "one", 1, now).is_ok());
+        // And it wears off
+        let later = now + Duration::from_secs(10);
+        assert!(chat.post("mick", "three", 1, later).is_ok());
+    }
+
+    #[test]
+    fn blocked_words_are_starred_out() {
+        let mut chat = chat();
+        let now = Instant::now();
+        assert_eq!(
+            chat.post("mick", "Feck off", 1, now).unwrap().text,
+            "**** off"
+        );
+        assert_eq!(
+            chat.post("john", "arsenal!", 1, now).unwrap().text,
+            "arsenal!"
+        );
+        assert_eq!(chat.post("john", "arse!", 1, now).unwrap().text, "****!");
+    }
+
+    #[test]
+    fn reactions_must_be_emoji() {
+        let mut chat = Chat::new(ChatSettings::default());
+        let now = Instant::now();
+        assert!(chat.react("mick", "👍", now).is_ok());
+        assert_eq!(chat.react("mick", "", now), Err(ChatError::InvalidEmoji));
+        assert_eq!(chat.react("mick", "lol", now), Err(ChatError::InvalidEmoji));
+        assert_eq!(
+            chat.react("mick", "👍👍👍👍👍👍👍👍👍", now),
+            Err(ChatError::InvalidEmoji)
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use super::history::HistoryItem;
+use super::rules::DeckExhausted;
+use deck::Card;
+use serde_json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+// Everything that happens in a game, in enough detail to replay it
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum GameEvent {
+    // A new deck was started. `reason` is None for the first deck of the game.
+    DeckShuffled {
+        cards: Vec<Card>,
+        reason: Option<DeckExhausted>,
+    },
+    PlayerJoined {
+        username: String,
+    },
+    PlayerLeft {
+        username: String,
+    },
+    // A player was put in a team, either when they joined or by the host
+    TeamAssigned {
+        username: String,
+        team: u8,
+    },
+    TurnPlayed {
+        item: HistoryItem,
+        next_player: Option<String>,
+        cards_left: usize,
+    },
+    // A card everyone guessed at once, with a history item for each player that guessed
+    RoundPlayed {
+        items: Vec<HistoryItem>,
+        cards_left: usize,
+    },
+    GameEnded,
+    // Everyone left so the game was thrown away
+    GameReset,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LoggedEvent {
+    // Milliseconds since the unix epoch
+    pub timestamp: u64,
+    pub event: GameEvent,
+}
+
+// Game ids are used as file names, so only allow the characters we generate them with
+pub fn is_valid_game_id(game_id: &str) -> bool {
+    !game_id.is_empty() && game_id.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+}
+
+fn log_path(dir: &Path, game_id: &str) -> PathBuf {
+    dir.join(format!("{}.jsonl", game_id))
+}
+
+// Appends the events for one game to `<dir>/<game_id>.jsonl`, one JSON event per line. Nothing is
+// written until the first turn is played so games that nobody plays don't leave files behind.
+pub struct EventLog {
+    path: PathBuf,
+    file: Option<File>,
+    pending: Vec<LoggedEvent>,
+}
+
+impl EventLog {
+    pub fn new(dir: &Path, game_id: &str) -> Self {
+        EventLog {
+            path: log_path(dir, game_id),
+            file: None,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_event(file: &mut File, event: &LoggedEvent) -> io::Result<()> {
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())
+    }
+
+    // Carry on writing to the log of a game that has already started
+    pub fn resume(dir: &Path, game_id: &str) -> io::Result<Self> {
+        let path = log_path(dir, game_id);
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(EventLog {
+            path,
+            file: Some(file),
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn append(&mut self, event: LoggedEvent) -> io::Result<()> {
+        if self.file.is_none() {
+            if !matches!(
+                event.event,
+                GameEvent::TurnPlayed { .. } | GameEvent::RoundPlayed { .. }
+            ) {
+                self.pending.push(event);
+                return Ok(());
+            }
+
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            for pending in self.pending.drain(..) {
+                Self::write_event(&mut file, &pending)?;
+            }
+            self.file = Some(file);
+        }
+
+        let file = self.file.as_mut().unwrap();
+        Self::write_event(file, &event)?;
+        // The log is used to rebuild the game if the server dies, so make sure it's on disk
+        file.sync_data()
+    }
+}
+
+pub fn read_events(dir: &Path, game_id: &str) -> io::Result<Vec<LoggedEvent>> {
+    let file = File::open(log_path(dir, game_id))?;
+    let mut events = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(serde_json::from_str(&line)?);
+    }
+    Ok(events)
+}
+
+// Whether the log runs to the end of the game, rather than stopping part way through
+pub fn game_finished(events: &[LoggedEvent]) -> bool {
+    events
+        .iter()
+        .any(|e| matches!(e.event, GameEvent::GameEnded | GameEvent::GameReset))
+}
+
+// The ids of every game with a log in the directory, oldest first
+pub fn list_games(dir: &Path) -> io::Result<Vec<String>> {
+    let mut game_ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map(|e| e == "jsonl").unwrap_or(false) {
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                if is_valid_game_id(stem) {
+                    game_ids.push(stem.to_string());
+                }
+            }
+        }
+    }
+    game_ids.sort();
+    Ok(game_ids)
+}
+
+// Like list_games, but leaves out the games that are still being played or were cut off by a
+// crash, so there's nothing half finished to replay
+pub fn list_finished_games(dir: &Path) -> io::Result<Vec<String>> {
+    let mut game_ids = list_games(dir)?;
+    game_ids.retain(|game_id| match read_events(dir, game_id) {
+        Ok(events) => game_finished(&events),
+        Err(e) => {
+            error!("Failed to read the log for {}: {}", game_id, e);
+            false
+        }
+    });
+    Ok(game_ids)
+}
+
+#[cfg(test)]
+mod event_log {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("red_or_black_{}_{}", name, process::id()))
+    }
+
+    fn logged(event: GameEvent) -> LoggedEvent {
+        LoggedEvent {
+            timestamp: 1,
+            event,
+        }
+    }
+
+    #[test]
+    fn valid_game_ids() {
+        assert!(is_valid_game_id("166a2b3c4d5-0f1e"));
+        assert!(!is_valid_game_id(""));
+        assert!(!is_valid_game_id("../profiles"));
+        assert!(!is_valid_game_id("abc.jsonl"));
+    }
+
+    #[test]
+    fn nothing_written_until_a_turn_is_played() {
+        let dir = temp_dir("unplayed");
+        let mut log = EventLog::new(&dir, "abc");
+        log.append(logged(GameEvent::PlayerJoined {
+            username: "mick".to_string(),
+        }))
+        .unwrap();
+        assert!(!log_path(&dir, "abc").exists());
+    }
+
+    #[test]
+    fn events_are_written_and_read_back() {
+        use deck::{Suit, Value};
+        use red_or_black::messages::CardColour;
+
+        let dir = temp_dir("written");
+        let card = Card {
+            value: Value::Ace,
+            suit: Suit::Spade,
+        };
+        let events = vec![
+            logged(GameEvent::PlayerJoined {
+                username: "mick".to_string(),
+            }),
+            logged(GameEvent::TurnPlayed {
+                item: HistoryItem {
+                    username: "mick".to_string(),
+                    guess: CardColour::Black,
+                    outcome: true,
+                    card,
+                    penalty: 10,
+                    turn_number: 1,
+                    timestamp: 1,
+                    team: None,
+                },
+                next_player: Some("mick".to_string()),
+                cards_left: 51,
+            }),
+            logged(GameEvent::GameEnded),
+        ];
+
+        let mut log = EventLog::new(&dir, "abc");
+        for event in events.clone() {
+            log.append(event).unwrap();
+        }
+
+        let read = read_events(&dir, "abc").unwrap();
+        let games = list_games(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read, events);
+        assert_eq!(games, vec!["abc".to_string()]);
+    }
+
+    #[test]
+    fn unfinished_games_are_not_listed_as_finished() {
+        let dir = temp_dir("unfinished");
+        let turn = || {
+            logged(GameEvent::RoundPlayed {
+                items: Vec::new(),
+                cards_left: 51,
+            })
+        };
+        let mut ended = EventLog::new(&dir, "a1");
+        ended.append(turn()).unwrap();
+        ended.append(logged(GameEvent::GameEnded)).unwrap();
+        let mut reset = EventLog::new(&dir, "b2");
+        reset.append(turn()).unwrap();
+        reset.append(logged(GameEvent::GameReset)).unwrap();
+        EventLog::new(&dir, "c3").append(turn()).unwrap();
+
+        let all = list_games(&dir).unwrap();
+        let finished = list_finished_games(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(all, vec!["a1", "b2", "c3"]);
+        assert_eq!(finished, vec!["a1", "b2"]);
+    }
+}
@@ -0,0 +1,158 @@
+use super::history::HistoryItem;
+use schemars::JsonSchema;
+use serde_json;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum ExportFormat {
+    JsonLines,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" => Ok(ExportFormat::JsonLines),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("Unknown export format '{}'", s)),
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "turn_number,timestamp,username,guess,outcome,card_value,card_suit,penalty,team";
+
+// Quote a field if it has anything in it that would break the CSV
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn to_csv(history: &[HistoryItem]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for item in history {
+        csv.push_str(&format!(
+            "{},{},{},{:?},{},{:?},{:?},{},{}\n",
+            item.turn_number,
+            item.timestamp,
+            csv_field(&item.username),
+            item.guess,
+            item.outcome,
+            item.card.value,
+            item.card.suit,
+            item.penalty,
+            // Empty when the game isn't played in teams
+            item.team.map_or(String::new(), |team| team.to_string()),
+        ));
+    }
+    csv
+}
+
+fn to_json_lines(history: &[HistoryItem]) -> String {
+    let mut lines = String::new();
+    for item in history {
+        lines.push_str(&serde_json::to_string(item).unwrap());
+        lines.push('\n');
+    }
+    lines
+}
+
+pub fn export_history(history: &[HistoryItem], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::JsonLines => to_json_lines(history),
+        ExportFormat::Csv => to_csv(history),
+    }
+}
+
+#[cfg(test)]
+mod formats {
+    use super::*;
+    use deck::{Card, Suit, Value};
+    use red_or_black::messages::CardColour;
+
+    fn history() -> Vec<HistoryItem> {
+        vec![
+            HistoryItem {
+                username: "mick".to_string(),
+                guess: CardColour::Red,
+                outcome: true,
+                card: Card {
+                    value: Value::Queen,
+                    suit: Suit::Heart,
+                },
+                penalty: 10,
+                turn_number: 1,
+                timestamp: 1_540_000_000_000,
+                team: None,
+            },
+            HistoryItem {
+                username: "Franco, \"The\" Begbie".to_string(),
+                guess: CardColour::Black,
+                outcome: false,
+                card: Card {
+                    value: Value::Two,
+                    suit: Suit::Diamond,
+                },
+                penalty: 10,
+                turn_number: 2,
+                timestamp: 1_540_000_005_000,
+                team: Some(2),
+            },
+        ]
+    }
+
+    #[test]
+    fn csv() {
+        let csv = export_history(&history(), ExportFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[1], "1,1540000000000,mick,Red,true,Queen,Heart,10,");
+        assert_eq!(
+            lines[2],
+            "2,1540000005000,\"Franco, \"\"The\"\" Begbie\",Black,false,Two,Diamond,10,2"
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let history = history();
+        let jsonl = export_history(&history, ExportFormat::JsonLines);
+        let items: Vec<HistoryItem> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(items, history);
+    }
+
+    #[test]
+    fn empty_history() {
+        assert_eq!(export_history(&[], ExportFormat::JsonLines), "");
+        assert_eq!(
+            export_history(&[], ExportFormat::Csv),
+            format!("{}\n", CSV_HEADER)
+        );
+    }
+}
@@ -1,44 +1,138 @@
+use red_or_black::access::{query_param, AccessSettings};
+use red_or_black::bots::{bot_name, BotStrategy, MAX_BOTS};
+use red_or_black::events::{self, is_valid_game_id};
+use red_or_black::heartbeat::{ping_payload, Heartbeat, HeartbeatSettings};
+use red_or_black::history::{history_page, DEFAULT_PAGE_SIZE};
+use red_or_black::export::{export_history, ExportFormat};
+use red_or_black::limits::{ConnectionLimits, IpConnections, LimitKind, MessageKind};
+use red_or_black::profiles::ProfileStore;
+use red_or_black::protocol::{decode_binary_request, decode_request, ClientSender, Format};
+use red_or_black::replay::{replay_steps, ReplayStep};
+use red_or_black::room::{room_replay_dir, Client, Room, RoomError, Rooms, DEFAULT_ROOM};
+use red_or_black::rules::{now_millis, DeckExhausted};
+use red_or_black::settings::GameSettings;
+use red_or_black::sweeper::{archive_room, Sweeper};
+use red_or_black::timeouts::{
+    BOT_TIMEOUT, GAME_TIMEOUT, PING_TIMEOUT, ROUND_TIMEOUT, SWEEP_TIMEOUT,
+};
 use red_or_black::RedOrBlack;
 
+#[cfg(feature = "tls")]
+use openssl::ssl::{SslAcceptor, SslStream};
 use serde_json;
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+#[cfg(feature = "tls")]
+use ws::util::TcpStream;
 use ws::util::Token;
 
 use super::messages::*;
 use std::cell::RefCell;
 use std::rc::Rc;
 use ws::Message::*;
-use ws::{CloseCode, Handler, Message, Result as WsResult, Sender};
+use ws::{
+    CloseCode, Frame, Handler, Handshake, Message, OpCode, Request, Response,
+    Result as WsResult,
+};
 
 #[derive(Clone)]
 pub struct Server {
-    pub out: Sender,
-    // The clients need to be modified by multiple connections
-    pub clients: Rc<RefCell<HashMap<Token, Client>>>,
-    // The game state also needs to be modified by multiple connections
-    pub game: Rc<RefCell<RedOrBlack>>,
+    pub out: ClientSender,
+    // Every room on the server, shared by every connection
+    pub rooms: Rc<RefCell<Rooms>>,
+    // The room this connection is in, or the default room until it logs in
+    pub room: Room,
+    // Lifetime stats for players, updated at the end of every game
+    pub profiles: Rc<RefCell<ProfileStore>>,
+    // Where recorded games are kept, None if games aren't recorded
+    pub replay_dir: Option<PathBuf>,
+    // The messages still to be sent for the replay this client is watching
+    pub replay: VecDeque<ReplayStep>,
+    // Used as the timeout token for replays, so timeouts from an old replay can be ignored
+    pub replay_generation: usize,
+    // How long players from a restored game have to reconnect before they lose their seat
+    pub reconnect_grace: Duration,
+    // Rate limits and the size limit for this connection
+    pub limits: ConnectionLimits,
+    // Open connections for each address, shared by every connection
+    pub ip_connections: Rc<RefCell<IpConnections>>,
+    // The address this connection is counted against, once it is open
+    pub peer_ip: Option<IpAddr>,
+    // Allowed origins and the join secret
+    pub access: Rc<AccessSettings>,
+    // Closes idle rooms and connections, shared by every connection
+    pub sweeper: Rc<RefCell<Sweeper>>,
+    // When this connection last sent anything, including pings
+    pub last_activity: Instant,
+    pub heartbeat_settings: Rc<HeartbeatSettings>,
+    // Pings sent to this connection and the pongs that came back
+    pub heartbeat: Heartbeat,
+    // The latest round trip time, kept here as well so it is known when the client logs in
+    pub heartbeat_latency: Option<u64>,
+    // Set once the connection has left its room, so it isn't removed twice
+    pub left: bool,
+    // The binary format the client asked for when it connected, if any
+    pub binary_format: Option<Format>,
+    // Used to encrypt the connection, if TLS is turned on
+    #[cfg(feature = "tls")]
+    pub tls: Option<Rc<SslAcceptor>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct Client {
-    pub username: String,
-    pub token: usize,
+// The most players to send back in a leaderboard
+const LEADERBOARD_SIZE: usize = 20;
+
+// The fastest a replay can be played
+const MAX_REPLAY_SPEED: f64 = 100.0;
+
+// How much a player's latency has to change by before everyone is sent the new player list
+const LATENCY_CHANGE_MS: u64 = 50;
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
 }
 
 impl Server {
     // Helper functions
-    fn unrecognised_msg() -> String {
-        let resp = SendableMessage::Error {
-            error: "Unrecognised message".to_string(),
-        };
-        serde_json::to_string(&resp).unwrap()
+
+    fn game_over(game: &RedOrBlack) -> SendableMessage {
+        SendableMessage::GameOver {
+            game_id: game.game_id().to_string(),
+            turns_played: game.turns_played(),
+            players: game.player_stats(),
+            teams: game.team_stats(),
+        }
     }
 
-    fn broadcast_players(&mut self) -> WsResult<()> {
-        let clients_map = self.clients.borrow();
-        let usernames: Vec<String> = clients_map.values().map(|c| c.username.clone()).collect();
-        self.out
-            .broadcast(SendableMessage::Players { players: usernames })
+    fn turn(username: String, game: &RedOrBlack) -> SendableMessage {
+        SendableMessage::Turn {
+            team: game.team_of(&username),
+            username,
+            turn_number: game.turn_number(),
+        }
+    }
+
+    fn team_scores(game: &RedOrBlack) -> Option<SendableMessage> {
+        if !game.teams_enabled() {
+            return None;
+        }
+        Some(SendableMessage::TeamScores {
+            teams: game.team_stats(),
+        })
+    }
+
+    // Everyone plays mode: who still has to guess the card that's up
+    fn round_started(game: &RedOrBlack) -> SendableMessage {
+        SendableMessage::RoundStarted {
+            turn_number: game.turn_number(),
+            window_ms: game.guess_window().map_or(0, millis),
+            waiting_for: game.waiting_for(),
+        }
+    }
+
+    fn broadcast_players(&mut self) {
+        self.room.broadcast(&self.room.players_message());
     }
     // end helpers
 
@@ -46,36 +140,734 @@ impl Server {
         use super::messages::ReceivableMessage::*;
         debug!("{:?}", msg);
         match msg {
-            Login { username: ref u } => {
-                self.add_client(u.to_string());
+            Login {
+                username: ref u,
+                ref player_id,
+                ref player_token,
+                ref room,
+                ref code,
+            } => {
+                if let Some(id) = player_id {
+                    let checked = self
+                        .profiles
+                        .borrow()
+                        .check_token(id, player_token.as_deref());
+                    if let Err(e) = checked {
+                        info!("Refused login as player {}: {:?}", id, e);
+                        return self.send_error(e.description());
+                    }
+                }
+                let room = room.as_deref().unwrap_or(DEFAULT_ROOM);
+                if self.join_room(room, code.as_deref()) {
+                    self.add_client(u.to_string());
+                    if let Some(id) = player_id {
+                        self.claim_player_id(u, id);
+                    }
+                }
+            }
+            Guess {
+                ref card_colour,
+                ref guess_id,
+                turn_number,
+            } => {
+                self.recieved_guess(card_colour, guess_id, *turn_number);
+            }
+            GetLeaderboard { ref room } => {
+                self.send_leaderboard(room);
+            }
+            GetProfile { ref player_id } => {
+                self.send_profile(player_id);
+            }
+            ExportHistory { format } => {
+                let format = *format;
+                let data = export_history(self.room.game.borrow().get_full_history(), format);
+                self.out
+                    .send(SendableMessage::HistoryExport { format, data })
+                    .unwrap();
+            }
+            ListReplays => {
+                self.send_replays();
+            }
+            Replay { ref game_id, speed } => {
+                self.start_replay(game_id, speed.unwrap_or(1.0));
+            }
+            Chat { ref text } => {
+                self.recieved_chat(text);
+            }
+            React {
+                ref emoji,
+                turn_number,
+            } => {
+                self.recieved_reaction(emoji, *turn_number);
+            }
+            CreateRoom {
+                ref room,
+                ref password,
+                invite_only,
+                private,
+                ref settings,
+            } => {
+                self.create_room(
+                    room,
+                    password.clone(),
+                    *invite_only,
+                    *private,
+                    settings.clone(),
+                );
+            }
+            RotateInviteCode => {
+                self.rotate_invite_code();
+            }
+            Spectate { ref room, ref code } => {
+                self.spectate(room, code.as_deref());
+            }
+            ListRooms => {
+                let rooms = self.rooms.borrow().list();
+                self.out.send(SendableMessage::Rooms { rooms }).unwrap();
+            }
+            SubscribeLobby => {
+                self.rooms.borrow_mut().subscribe(self.out.clone());
+                let rooms = self.rooms.borrow().list();
+                self.out.send(SendableMessage::Rooms { rooms }).unwrap();
+            }
+            UnsubscribeLobby => {
+                self.rooms.borrow_mut().unsubscribe(self.out.token());
+            }
+            AddBot {
+                strategy,
+                ref name,
+            } => {
+                self.add_bot(*strategy, name);
+            }
+            RemoveBot { ref username } => {
+                self.remove_bot(username);
+            }
+            AssignTeam { ref username, team } => {
+                self.assign_team(username, *team);
+            }
+            GetHistory { before_turn, limit } => {
+                let limit = limit.map_or(DEFAULT_PAGE_SIZE, usize::from);
+                self.send_history_page(*before_turn, limit);
+            }
+        }
+    }
+
+    fn add_bot(&mut self, strategy: BotStrategy, name: &Option<String>) {
+        if self.logged_in_username().is_none() || !self.room.is_host(self.out.token()) {
+            return self.send_error(RoomError::NotHost.description());
+        }
+        if self.room.bots.borrow().len() >= MAX_BOTS {
+            return self.send_error(RoomError::TooManyBots.description());
+        }
+        let username = {
+            let game = self.room.game.borrow();
+            let username = match name.as_ref().map(|n| n.trim()).filter(|n| !n.is_empty()) {
+                Some(name) => name.to_string(),
+                None => bot_name(strategy, game.usernames()),
+            };
+            if game.usernames().contains(&username) {
+                drop(game);
+                return self.send_error(RoomError::NameTaken.description());
+            }
+            username
+        };
+        info!("Adding {:?} bot {} to {}", strategy, username, self.room.name);
+        self.room
+            .bots
+            .borrow_mut()
+            .insert(username.clone(), strategy);
+        self.room.game.borrow_mut().add_player(username);
+        self.broadcast_players();
+        self.notify_lobby();
+        self.room.schedule_bot_turn();
+        self.bots_guess();
+    }
+
+    fn remove_bot(&mut self, username: &str) {
+        if !self.room.is_host(self.out.token()) {
+            return self.send_error(RoomError::NotHost.description());
+        }
+        if self.room.bots.borrow_mut().remove(username).is_none() {
+            return self.send_error(RoomError::NoSuchBot.description());
+        }
+        info!("Removing bot {} from {}", username, self.room.name);
+        let changed_turn = self.room.game.borrow_mut().remove_player(username);
+        if changed_turn && !self.room.game.borrow().everyone_plays() {
+            let mut game = self.room.game.borrow_mut();
+            if let Some(p) = game.get_current_player().cloned() {
+                self.room.broadcast(&Server::turn(p, &game));
+            }
+        }
+        self.broadcast_players();
+        self.notify_lobby();
+        self.room.schedule_bot_turn();
+        self.reveal_if_everyone_guessed();
+    }
+
+    fn assign_team(&mut self, username: &str, team: u8) {
+        if !self.room.is_host(self.out.token()) {
+            return self.send_error(RoomError::NotHost.description());
+        }
+        let assigned = self.room.game.borrow_mut().assign_team(username, team);
+        if let Err(e) = assigned {
+            return self.send_error(e.description());
+        }
+        info!("Moved {} to team {} in {}", username, team, self.room.name);
+        self.broadcast_players();
+        let mut game = self.room.game.borrow_mut();
+        if let Some(scores) = Server::team_scores(&game) {
+            self.room.broadcast(&scores);
+        }
+        // Moving players around can change whose turn it is
+        if !game.is_finished() && !game.everyone_plays() {
+            if let Some(p) = game.get_current_player().cloned() {
+                self.room.broadcast(&Server::turn(p, &game));
+            }
+        }
+        drop(game);
+        self.room.schedule_bot_turn();
+    }
+
+    // Play the turn of the bot this connection was asked to play for
+    fn play_bot_turn(&mut self) {
+        let turn = self.room.game.borrow().turn_number();
+        match self.room.bot_turn.get() {
+            Some((scheduled, token)) if scheduled == turn && token == self.out.token() => {}
+            _ => return,
+        }
+        self.room.bot_turn.set(None);
+        if let Some((username, strategy)) = self.room.bot_to_play() {
+            let guess = strategy.guess(&self.room.game.borrow());
+            info!("{} guessed {:?}", username, guess);
+            self.play_turn(&guess);
+        }
+    }
+
+    fn schedule_sweep(&mut self) -> WsResult<()> {
+        let interval = self.sweeper.borrow().settings.interval;
+        let ms = (interval.as_secs() * 1000).max(1000);
+        self.out.timeout(ms, SWEEP_TIMEOUT)
+    }
+
+    // Close this connection if it has gone quiet, and close any rooms that have. Every
+    // connection does this, but the rooms are only swept once each interval.
+    fn sweep(&mut self) -> WsResult<()> {
+        let now = Instant::now();
+        if self
+            .sweeper
+            .borrow()
+            .is_connection_idle(self.last_activity, now)
+        {
+            let closed = self.sweeper.borrow_mut().connection_closed();
+            info!(
+                "Closing idle connection from {:?} ({} idle connections closed)",
+                self.peer_ip, closed
+            );
+            return self.out.close_with_reason(CloseCode::Away, "Idle");
+        }
+
+        let due = self.sweeper.borrow_mut().is_due(now);
+        if !due {
+            return self.schedule_sweep();
+        }
+        let room_idle = self.sweeper.borrow().settings.room_idle;
+        let expired = self.rooms.borrow_mut().expire_idle(room_idle, now);
+        for room in &expired {
+            self.evict_room(room);
+        }
+        if !expired.is_empty() {
+            self.notify_lobby();
+        }
+        self.schedule_sweep()
+    }
+
+    // Archive a room that has been taken out of the registry, and send everyone in it away
+    fn evict_room(&self, room: &Room) {
+        let archive_dir = self.sweeper.borrow().settings.archive_dir.clone();
+        if let Some(dir) = archive_dir {
+            match archive_room(&dir, room) {
+                Ok(Some(path)) => info!("Archived {} to {}", room.name, path.display()),
+                Ok(None) => {}
+                Err(e) => error!("Failed to archive {}: {}", room.name, e),
+            }
+        }
+        let evicted = self.sweeper.borrow_mut().room_evicted();
+        info!("Closed idle room {} ({} rooms closed)", room.name, evicted);
+
+        room.broadcast(&SendableMessage::Error {
+            error: "The room was closed after being idle".to_string(),
+        });
+        let clients: Vec<ClientSender> = room
+            .clients
+            .borrow()
+            .values()
+            .map(|c| c.sender.clone())
+            .collect();
+        let spectators: Vec<ClientSender> = room.spectators.borrow().values().cloned().collect();
+        for sender in clients.iter().chain(spectators.iter()) {
+            if let Err(e) = sender.close_with_reason(CloseCode::Away, "Room closed") {
+                error!("Failed to close connection: {}", e);
+            }
+        }
+    }
+
+    fn schedule_ping(&mut self) -> WsResult<()> {
+        let interval = self.heartbeat_settings.ping_interval;
+        if interval == Duration::from_secs(0) {
+            return Ok(());
+        }
+        let ms = interval.as_secs() * 1000 + u64::from(interval.subsec_millis());
+        self.out.timeout(ms, PING_TIMEOUT)
+    }
+
+    // Ping the client, or give up on it if it has stopped answering. Clients that vanish
+    // without closing the connection would otherwise keep their seat forever.
+    fn send_ping(&mut self) -> WsResult<()> {
+        if !self.heartbeat.ping() {
+            warn!(
+                "{:?} missed {} pongs, disconnecting",
+                self.logged_in_username().unwrap_or_default(),
+                self.heartbeat.missed_pongs()
+            );
+            self.leave();
+            return self
+                .out
+                .close_with_reason(CloseCode::Away, "Missed pongs");
+        }
+        self.out.ping(ping_payload(now_millis()))?;
+        self.schedule_ping()
+    }
+
+    fn recieved_pong(&mut self, payload: &[u8]) {
+        let latency = match self.heartbeat.pong(payload, now_millis()) {
+            Some(latency) => latency,
+            None => return,
+        };
+        let previous = self.heartbeat_latency.replace(latency);
+        let changed = {
+            let mut clients = self.room.clients.borrow_mut();
+            match clients.get_mut(&self.out.token()) {
+                Some(client) => {
+                    client.latency_ms = Some(latency);
+                    previous
+                        .is_none_or(|p| p.max(latency) - p.min(latency) >= LATENCY_CHANGE_MS)
+                }
+                None => false,
+            }
+        };
+        if changed {
+            self.broadcast_players();
+        }
+    }
+
+    // Take this connection out of its room, once
+    fn leave(&mut self) {
+        if self.left {
+            return;
+        }
+        self.left = true;
+        self.remove_client();
+        self.rooms.borrow_mut().connection_closed(self.out.token());
+        if let Some(ip) = self.peer_ip.take() {
+            self.ip_connections.borrow_mut().disconnect(ip);
+        }
+    }
+
+    fn notify_lobby(&self) {
+        self.rooms.borrow_mut().notify_lobby();
+    }
+
+    fn spectate(&mut self, name: &str, code: Option<&str>) {
+        if !self.join_room(name, code) {
+            return;
+        }
+        info!("Spectating {}", self.room.name);
+        self.room
+            .spectators
+            .borrow_mut()
+            .insert(self.out.token(), self.out.clone());
+        self.out.send(self.room.players_message()).unwrap();
+        self.send_snapshot();
+        self.notify_lobby();
+    }
+
+    // Move this connection into a room, if it has the right code. Returns false if it can't
+    // join.
+    fn join_room(&mut self, name: &str, code: Option<&str>) -> bool {
+        if self.logged_in_username().is_some() {
+            info!("Already logged in to {}, not joining {}", self.room.name, name);
+            return false;
+        }
+        let found = self.rooms.borrow().get(name);
+        let room = match found {
+            Some(room) => room,
+            None => {
+                self.send_error(RoomError::NoSuchRoom.description());
+                return false;
+            }
+        };
+        let checked = room.access.borrow().check(code);
+        if let Err(e) = checked {
+            info!("Refused entry to {}: {:?}", room.name, e);
+            self.send_error(e.description());
+            return false;
+        }
+        // Stop watching the room this connection was spectating
+        self.room
+            .spectators
+            .borrow_mut()
+            .remove(&self.out.token());
+        self.room = room;
+        true
+    }
+
+    fn create_room(
+        &mut self,
+        name: &str,
+        password: Option<String>,
+        invite_only: bool,
+        private: bool,
+        settings: Option<GameSettings>,
+    ) {
+        let created = self.rooms.borrow_mut().create(
+            name,
+            password,
+            invite_only,
+            private,
+            settings,
+            self.out.token(),
+        );
+        match created {
+            Ok(room) => {
+                let invite_code = room.access.borrow().invite_code.clone();
+                self.out
+                    .send(SendableMessage::RoomCreated {
+                        room: room.name.clone(),
+                        invite_code,
+                    }).unwrap();
+                self.notify_lobby();
+            }
+            Err(e) => self.send_error(e.description()),
+        }
+    }
+
+    fn rotate_invite_code(&mut self) {
+        let rotated = self
+            .room
+            .access
+            .borrow_mut()
+            .rotate_invite_code(self.out.token());
+        match rotated {
+            Ok(invite_code) => {
+                info!("Invite code for {} changed", self.room.name);
+                self.out
+                    .send(SendableMessage::InviteCode {
+                        room: self.room.name.clone(),
+                        invite_code,
+                    }).unwrap();
+            }
+            Err(e) => self.send_error(e.description()),
+        }
+    }
+
+    fn logged_in_username(&self) -> Option<String> {
+        self.room
+            .clients
+            .borrow()
+            .get(&self.out.token())
+            .map(|c| c.username.clone())
+    }
+
+    fn recieved_chat(&mut self, text: &str) {
+        let username = match self.logged_in_username() {
+            Some(username) => username,
+            None => return self.send_error("You need to log in to chat"),
+        };
+        let posted = self
+            .room
+            .chat
+            .borrow_mut()
+            .post(&username, text, now_millis(), Instant::now());
+        match posted {
+            Ok(message) => self.room.broadcast(&SendableMessage::Chat(message)),
+            Err(e) => self.send_error(e.description()),
+        }
+    }
+
+    fn recieved_reaction(&mut self, emoji: &str, turn_number: u16) {
+        let username = match self.logged_in_username() {
+            Some(username) => username,
+            None => return self.send_error("You need to log in to react"),
+        };
+        let turns_played = self.room.game.borrow().turns_played();
+        if turn_number == 0 || turn_number as usize > turns_played {
+            return self.send_error("No turn with that number");
+        }
+        let reacted = self
+            .room
+            .chat
+            .borrow_mut()
+            .react(&username, emoji, Instant::now());
+        match reacted {
+            Ok(()) => self.room.broadcast(&SendableMessage::Reaction {
+                username,
+                emoji: emoji.to_string(),
+                turn_number,
+            }),
+            Err(e) => self.send_error(e.description()),
+        }
+    }
+
+    fn send_limit_exceeded(
+        &mut self,
+        limit: LimitKind,
+        error: &str,
+        message_kind: Option<MessageKind>,
+        retry_after: Option<Duration>,
+    ) {
+        self.out
+            .send(SendableMessage::LimitExceeded {
+                limit,
+                error: error.to_string(),
+                message_kind,
+                retry_after_ms: retry_after
+                    .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis())),
+            }).unwrap();
+    }
+
+    // Returns false if the message should be dropped, disconnecting clients that keep breaking
+    // the rate limits
+    fn check_rate_limit(&mut self, kind: MessageKind) -> WsResult<bool> {
+        let retry_after = match self.limits.check_rate(kind, Instant::now()) {
+            Ok(()) => return Ok(true),
+            Err(retry_after) => retry_after,
+        };
+        self.send_limit_exceeded(
+            LimitKind::RateLimit,
+            "You're sending messages too quickly",
+            Some(kind),
+            Some(retry_after),
+        );
+        if self.limits.add_violation() {
+            warn!(
+                "Disconnecting {:?} for repeatedly breaking the rate limits",
+                self.peer_ip
+            );
+            self.out
+                .close_with_reason(CloseCode::Policy, "Too many messages")?;
+        }
+        Ok(false)
+    }
+
+    fn send_error(&mut self, error: &str) {
+        self.out
+            .send(SendableMessage::Error {
+                error: error.to_string(),
+            }).unwrap();
+    }
+
+    // Replays are of games played in the room this connection is in
+    fn room_replay_dir(&self) -> Option<PathBuf> {
+        self.replay_dir
+            .as_ref()
+            .map(|dir| room_replay_dir(dir, &self.room.name))
+    }
+
+    fn send_replays(&mut self) {
+        let game_ids = match self.room_replay_dir() {
+            Some(ref dir) if dir.exists() => {
+                events::list_finished_games(dir).unwrap_or_else(|e| {
+                    error!("Failed to list replays: {}", e);
+                    Vec::new()
+                })
+            }
+            Some(_) => Vec::new(),
+            None => Vec::new(),
+        };
+        self.out
+            .send(SendableMessage::Replays { game_ids })
+            .unwrap();
+    }
+
+    fn start_replay(&mut self, game_id: &str, speed: f64) {
+        if !(speed > 0.0 && speed <= MAX_REPLAY_SPEED) {
+            self.send_error("Invalid replay speed");
+            return;
+        }
+        let loaded = match self.room_replay_dir() {
+            Some(ref dir) if is_valid_game_id(game_id) => events::read_events(dir, game_id),
+            Some(_) => {
+                self.send_error("No game with that id");
+                return;
+            }
+            None => {
+                self.send_error("Games aren't being recorded");
+                return;
+            }
+        };
+        match loaded {
+            Ok(logged_events) => {
+                info!("Replaying game {} at {}x speed", game_id, speed);
+                self.replay = replay_steps(game_id, &logged_events, speed);
+                self.replay_generation += 1;
+                self.schedule_replay_step();
             }
-            Guess { ref card_colour } => {
-                self.recieved_guess(card_colour);
+            Err(e) => {
+                info!("Failed to load game {} for replay: {}", game_id, e);
+                self.send_error("No game with that id");
             }
         }
     }
 
+    fn schedule_replay_step(&mut self) {
+        if let Some(step) = self.replay.front() {
+            self.out
+                .timeout(step.delay_ms, Token(self.replay_generation))
+                .unwrap();
+        }
+    }
+
+    // Serve the game history of a room as a file download, e.g. GET /history.csv?room=main. Rooms
+    // with a password or invite code need it passed as `code`.
+    fn history_download(&self, resource: &str) -> Option<Response> {
+        let path = resource.split('?').next().unwrap_or("");
+        if !path.starts_with("/history.") {
+            return None;
+        }
+        let format = path["/history.".len()..].parse::<ExportFormat>().ok()?;
+        let name = query_param(resource, "room").unwrap_or_else(|| DEFAULT_ROOM.to_string());
+        let room = match self.rooms.borrow().get(&name) {
+            Some(room) => room,
+            None => return Some(Response::new(404, "Not Found", b"No such room".to_vec())),
+        };
+        let checked = room
+            .access
+            .borrow()
+            .check(query_param(resource, "code").as_deref());
+        if let Err(e) = checked {
+            return Some(Response::new(
+                403,
+                "Forbidden",
+                e.description().as_bytes().to_vec(),
+            ));
+        }
+        let data = export_history(room.game.borrow().get_full_history(), format);
+        let mut response = Response::new(200, "OK", data.into_bytes());
+        {
+            let headers = response.headers_mut();
+            headers.push((
+                "Content-Type".to_string(),
+                format.content_type().as_bytes().to_vec(),
+            ));
+            headers.push((
+                "Content-Disposition".to_string(),
+                format!(
+                    "attachment; filename=\"red_or_black_history.{}\"",
+                    format.extension()
+                ).into_bytes(),
+            ));
+        }
+        Some(response)
+    }
+
+    // Serve the public rooms as JSON from GET /rooms
+    fn room_listing(&self, resource: &str) -> Option<Response> {
+        if resource.split('?').next() != Some("/rooms") {
+            return None;
+        }
+        let rooms = self.rooms.borrow().list();
+        let mut response = Response::new(200, "OK", serde_json::to_vec(&rooms).unwrap());
+        response
+            .headers_mut()
+            .push(("Content-Type".to_string(), b"application/json".to_vec()));
+        Some(response)
+    }
+
+    fn send_leaderboard(&mut self, room: &Option<String>) {
+        let entries = self
+            .profiles
+            .borrow()
+            .leaderboard(room.as_ref().map(|r| r.as_str()), LEADERBOARD_SIZE);
+        self.out
+            .send(SendableMessage::Leaderboard {
+                room: room.clone(),
+                entries,
+            }).unwrap();
+    }
+
+    // Track the player's stats under their id, once they've logged in
+    fn claim_player_id(&mut self, username: &str, player_id: &str) {
+        if self.logged_in_username().as_deref() != Some(username) {
+            return;
+        }
+        let mut profiles = self.profiles.borrow_mut();
+        profiles.identify(&self.room.name, username, player_id);
+        if let Some(token) = profiles.issue_token(player_id) {
+            self.out
+                .send(SendableMessage::PlayerToken {
+                    player_id: player_id.to_string(),
+                    token,
+                }).unwrap();
+        }
+    }
+
+    fn send_profile(&mut self, player_id: &str) {
+        let message = match self.profiles.borrow().get_profile(player_id) {
+            Some(profile) => SendableMessage::Profile(profile.clone()),
+            None => SendableMessage::Error {
+                error: "No profile for that player".to_string(),
+            },
+        };
+        self.out.send(message).unwrap();
+    }
+
+    // Add the stats from a finished game to the player profiles
+    fn record_game(&self, game: &RedOrBlack) {
+        self.profiles
+            .borrow_mut()
+            .record_game(&self.room.name, &game.player_stats());
+    }
+
     fn send_card_history(&mut self) {
-        let game = self.game.borrow();
+        let game = self.room.game.borrow();
         let history = game.get_card_history().clone();
         self.out
             .send(SendableMessage::RequestHistory { history })
             .unwrap();
     }
 
-    fn send_game_history(&mut self) {
-        let game = self.game.borrow();
-        let history = game.get_game_history().clone();
+    fn send_history_page(&mut self, before_turn: Option<u16>, limit: usize) {
+        let game = self.room.game.borrow();
+        let (items, has_more) = history_page(game.get_full_history(), before_turn, limit);
         self.out
-            .send(SendableMessage::GameHistory { history })
+            .send(SendableMessage::HistoryPage { items, has_more })
             .unwrap();
     }
 
+    // Players from a restored game that haven't come back are removed once the grace period is
+    // up, moving the turn on if it was theirs
+    fn expire_unclaimed_players(&mut self) {
+        let mut game = self.room.game.borrow_mut();
+        if !game.expire_unclaimed_players(self.reconnect_grace) {
+            return;
+        }
+        if game.everyone_plays() {
+            drop(game);
+            self.reveal_if_everyone_guessed();
+        } else if let Some(p) = game.get_current_player().cloned() {
+            self.room.broadcast(&Server::turn(p, &game));
+        }
+    }
+
     fn add_client(&mut self, username: String) {
+        self.expire_unclaimed_players();
+        if self.room.bots.borrow().contains_key(&username) {
+            return self.send_error(RoomError::NameTaken.description());
+        }
         // scope for clients mutable borrow
         info!("Adding client {}", username);
         {
-            let mut clients = self.clients.borrow_mut();
+            let mut clients = self.room.clients.borrow_mut();
             if clients.get(&self.out.token()).is_some() {
                 // Client already exists.. do nothing
                 info!("{} is already logged in... doing nothing.", username);
@@ -87,50 +879,86 @@ impl Server {
                 Client {
                     username: username.clone(),
                     token: self.out.token().0,
+                    sender: self.out.clone(),
+                    latency_ms: self.heartbeat_latency,
                 },
             );
         }
 
-        // scope for game mutable borrow
-        let current_player = {
-            let mut game = self.game.borrow_mut();
-            game.add_player(username);
-            game.get_current_player().unwrap().clone()
-        };
+        // The first player in a room without a host runs it
+        {
+            let mut access = self.room.access.borrow_mut();
+            if access.host.is_none() {
+                access.host = Some(self.out.token());
+            }
+        }
 
-        let penalty = self.game.borrow().get_penalty();
+        self.room.game.borrow_mut().add_player(username);
 
         // Send out updated player list
-        self.broadcast_players().unwrap();
+        self.broadcast_players();
 
         // Tell the new player that they are logged in
         self.out.send(SendableMessage::LoggedIn).unwrap();
 
+        self.send_snapshot();
+        self.notify_lobby();
+        if let Some(ms) = self.game_end_ms() {
+            if let Err(e) = self.out.timeout(ms, GAME_TIMEOUT) {
+                error!("Failed to schedule the end of the game: {}", e);
+            }
+        }
+    }
+
+    // Send everything someone joining the room needs to catch up with the game
+    fn send_snapshot(&mut self) {
+        let penalty = self.room.game.borrow().get_penalty();
+
         // Tell the new player the penalty
         self.out.send(SendableMessage::Penalty { penalty }).unwrap();
 
         // Send the new player the last three cards
         self.send_card_history();
 
-        // Send the new player the game history
-        self.send_game_history();
+        // Send the new player the latest turns, they can fetch the rest a page at a time
+        self.send_history_page(None, DEFAULT_PAGE_SIZE);
 
         // Send now many cards are left
         self.out
             .send(SendableMessage::CardsLeft {
-                cards_left: self.game.borrow().cards_left(),
+                cards_left: self.room.game.borrow().cards_left(),
             }).unwrap();
 
-        // Tell the player whose turn it is
+        // Send the recent chat
+        let messages = self.room.chat.borrow().get_history().iter().cloned().collect();
         self.out
-            .send(SendableMessage::Turn {
-                username: current_player,
-            }).unwrap();
+            .send(SendableMessage::ChatHistory { messages })
+            .unwrap();
+
+        // Send the odds of the next card, if card counting is on
+        if let Some(stats) = self.room.game.borrow().deck_stats() {
+            self.out.send(SendableMessage::DeckStats(stats)).unwrap();
+        }
+
+        // Send the team scores, if the game is played in teams
+        if let Some(scores) = Server::team_scores(&self.room.game.borrow()) {
+            self.out.send(scores).unwrap();
+        }
+
+        // Tell the player whose turn it is, or how the game went if it's over
+        let mut game = self.room.game.borrow_mut();
+        if game.is_finished() {
+            self.out.send(Server::game_over(&game)).unwrap();
+        } else if game.everyone_plays() {
+            self.out.send(Server::round_started(&game)).unwrap();
+        } else if let Some(current_player) = game.get_current_player().cloned() {
+            self.out.send(Server::turn(current_player, &game)).unwrap();
+        }
     }
 
     fn check_is_players_go(&mut self) -> bool {
-        let clients = self.clients.borrow();
-        let mut game = self.game.borrow_mut();
+        let clients = self.room.clients.borrow();
+        let mut game = self.room.game.borrow_mut();
         if let (Some(client), Some(username)) =
             (clients.get(&self.out.token()), game.get_current_player())
         {
@@ -141,71 +969,517 @@ impl Server {
         false
     }
 
-    fn recieved_guess(&mut self, card_colour: &CardColour) {
-        if !self.check_is_players_go() {
-            // It's not this players go, do nothing.
+    fn recieved_guess(
+        &mut self,
+        card_colour: &CardColour,
+        guess_id: &Option<String>,
+        turn_number: Option<u16>,
+    ) {
+        self.expire_unclaimed_players();
+        let current_turn = self.room.game.borrow().turn_number();
+        let everyone_plays = self.room.game.borrow().everyone_plays();
+        let username = self.logged_in_username();
+        // A guess sent again, e.g. after a reconnect, is acknowledged but not played twice. Guesses
+        // without a turn number are taken to be for the turn being played.
+        let accepted = match (&username, guess_id) {
+            (Some(u), Some(id)) => self
+                .room
+                .accepted_guesses
+                .borrow()
+                .get(u)
+                .filter(|&(accepted, turn)| {
+                    accepted == id && *turn == turn_number.unwrap_or(current_turn)
+                }).map(|&(_, turn)| turn),
+            _ => None,
+        };
+        if let Some(turn) = accepted {
+            return self.send_ack(guess_id, turn);
+        }
+        let rejection = match username {
+            None => Some(NackReason::NotLoggedIn),
+            Some(_) if self.room.game.borrow().is_finished() => Some(NackReason::GameOver),
+            Some(_) if turn_number.is_some_and(|t| t != current_turn) => {
+                Some(NackReason::WrongTurn)
+            }
+            Some(ref u) if everyone_plays && !self.room.game.borrow().usernames().contains(u) => {
+                Some(NackReason::NotPlaying)
+            }
+            Some(ref u) if everyone_plays && self.room.game.borrow().has_guessed(u) => {
+                Some(NackReason::AlreadyGuessed)
+            }
+            Some(_) if !everyone_plays && !self.check_is_players_go() => {
+                Some(NackReason::NotYourTurn)
+            }
+            Some(_) => None,
+        };
+        if let Some(reason) = rejection {
+            self.out
+                .send(SendableMessage::Nack {
+                    guess_id: guess_id.clone(),
+                    turn_number: current_turn,
+                    reason,
+                    error: reason.description().to_string(),
+                }).unwrap();
+            return;
+        }
+        if let (Some(ref u), Some(ref id)) = (&username, guess_id) {
+            self.room
+                .accepted_guesses
+                .borrow_mut()
+                .insert(u.clone(), (id.clone(), current_turn));
+        }
+        self.send_ack(guess_id, current_turn);
+        match username {
+            Some(ref username) if everyone_plays => self.collect_guess(username, card_colour),
+            _ => self.play_turn(card_colour),
+        }
+    }
+
+    fn send_ack(&mut self, guess_id: &Option<String>, turn_number: u16) {
+        self.out
+            .send(SendableMessage::Ack {
+                guess_id: guess_id.clone(),
+                turn_number,
+            }).unwrap();
+    }
+
+    // Play the current player's turn, whether they are a person or a bot
+    fn play_turn(&mut self, card_colour: &CardColour) {
+        let mut game = self.room.game.borrow_mut();
+        if game.is_finished() {
+            drop(game);
+            self.send_error("The game is over");
             return;
         }
-        let mut game = self.game.borrow_mut();
         let current_player = game.get_current_player().unwrap().clone();
-        let (correct, penalty, next_player, card, cards_left) = game.play_turn(card_colour);
+        let outcome = game.play_turn(card_colour);
         let message = SendableMessage::GuessResult {
-            correct,
-            card,
-            penalty,
+            correct: outcome.correct,
+            card: outcome.card,
+            penalty: outcome.penalty,
             username: current_player.clone(),
             guess: card_colour.clone(),
+            team: outcome.item.team,
+            shares: outcome.shares,
         };
-        info!("{} was {}", current_player, correct);
-        // Broadcast the result to everyone.
-        self.out.broadcast(message).unwrap();
-        self.out
-            .broadcast(SendableMessage::CardsLeft { cards_left })
-            .unwrap();
-        self.out
-            .broadcast(SendableMessage::Turn {
-                username: next_player.unwrap().clone(),
-            }).unwrap();
+        info!("{} was {}", current_player, outcome.correct);
+        // Broadcast the result to everyone in the room, along with the changes to the histories
+        self.room.broadcast(&message);
+        self.room.broadcast(&SendableMessage::HistoryAppended(outcome.item));
+        self.room.broadcast(&SendableMessage::CardHistoryShifted { card: outcome.card });
+        self.broadcast_deck(&game, outcome.deck_exhausted);
+        if game.is_finished() {
+            self.record_game(&game);
+            self.room.broadcast(&Server::game_over(&game));
+        } else {
+            self.room
+                .broadcast(&Server::turn(outcome.next_player.unwrap(), &game));
+        }
+        let first_turn = game.turns_played() == 1;
+        drop(game);
+        self.notify_lobby();
+        if first_turn {
+            self.schedule_game_end();
+        }
+        self.room.schedule_bot_turn();
+    }
+
+    // How long until a game with a time limit is out of time, once it has started
+    fn game_end_ms(&self) -> Option<u64> {
+        let game = self.room.game.borrow();
+        if game.is_finished() {
+            return None;
+        }
+        game.deadline()
+            .map(|deadline| millis(deadline.saturating_duration_since(Instant::now())) + 1)
+    }
+
+    // Games with a time limit end on time even if nobody is playing. Every player's connection is
+    // asked to check, so the game still ends if some of them leave. Players that join later
+    // schedule their own check.
+    fn schedule_game_end(&self) {
+        if let Some(ms) = self.game_end_ms() {
+            for client in self.room.clients.borrow().values() {
+                if let Err(e) = client.sender.timeout(ms, GAME_TIMEOUT) {
+                    error!("Failed to schedule the end of the game: {}", e);
+                }
+            }
+        }
+    }
+
+    // Whichever connection goes first ends the game. Checks left over from an earlier game, or
+    // that fire a little early, are rescheduled or ignored.
+    fn game_timed_out(&mut self) {
+        let ended = self.room.game.borrow_mut().check_deadline(Instant::now());
+        if ended {
+            let game = self.room.game.borrow();
+            self.record_game(&game);
+            self.room.broadcast(&Server::game_over(&game));
+            drop(game);
+            self.notify_lobby();
+        } else if let Some(ms) = self.game_end_ms() {
+            if let Err(e) = self.out.timeout(ms, GAME_TIMEOUT) {
+                error!("Failed to schedule the end of the game: {}", e);
+            }
+        }
+    }
+
+    // Tell everyone what's left in the deck after a card was drawn, and the team scores
+    fn broadcast_deck(&self, game: &RedOrBlack, deck_exhausted: Option<DeckExhausted>) {
+        if let Some(deck_exhausted) = deck_exhausted {
+            self.room
+                .broadcast(&SendableMessage::DeckExhausted(deck_exhausted));
+        }
+        self.room.broadcast(&SendableMessage::CardsLeft {
+            cards_left: game.cards_left(),
+        });
+        if let Some(stats) = game.deck_stats() {
+            self.room.broadcast(&SendableMessage::DeckStats(stats));
+        }
+        if let Some(scores) = Server::team_scores(game) {
+            self.room.broadcast(&scores);
+        }
+    }
+
+    // Everyone plays mode: lock in a player's guess for the card that's up. The first guess
+    // starts the window for everyone else, and the card is turned over once they're all in.
+    fn collect_guess(&mut self, username: &str, card_colour: &CardColour) {
+        let now = Instant::now();
+        let is_bot = self.room.bots.borrow().contains_key(username);
+        let mut game = self.room.game.borrow_mut();
+        let window_was_open = game.round_closes_at().is_some();
+        let submitted = if is_bot {
+            game.submit_bot_guess(username, card_colour.clone())
+        } else {
+            game.submit_guess(username, card_colour.clone(), now)
+        };
+        let opens_window = !window_was_open && game.round_closes_at().is_some();
+        let complete = match submitted {
+            Ok(complete) => complete,
+            Err(e) => {
+                warn!("Couldn't collect {}'s guess: {:?}", username, e);
+                return;
+            }
+        };
+        let closes_in_ms = game
+            .round_closes_at()
+            .map_or(0, |t| millis(t.saturating_duration_since(now)));
+        self.room.broadcast(&SendableMessage::GuessReceived {
+            username: username.to_string(),
+            turn_number: game.turn_number(),
+            waiting_for: game.waiting_for(),
+            closes_in_ms,
+        });
+        drop(game);
+        if complete {
+            self.reveal_round();
+        } else if opens_window {
+            self.room.schedule_round_reveal(closes_in_ms);
+        }
+    }
+
+    // Everyone plays mode: turn over the card and tell everyone how they did
+    fn reveal_round(&mut self) {
+        let mut game = self.room.game.borrow_mut();
+        let outcome = match game.reveal() {
+            Some(outcome) => outcome,
+            None => return,
+        };
+        info!(
+            "{:?} revealed to {} players, everyone right: {}",
+            outcome.card,
+            outcome.items.len(),
+            outcome.everyone_right
+        );
+        self.room.broadcast(&SendableMessage::RoundResult {
+            turn_number: outcome.items[0].turn_number,
+            card: outcome.card,
+            penalty: outcome.penalty,
+            everyone_right: outcome.everyone_right,
+            guesses: outcome.items.clone(),
+            shares: outcome.shares,
+        });
+        for item in outcome.items {
+            self.room.broadcast(&SendableMessage::HistoryAppended(item));
+        }
+        self.room.broadcast(&SendableMessage::CardHistoryShifted { card: outcome.card });
+        self.room.broadcast(&SendableMessage::Penalty {
+            penalty: game.get_penalty(),
+        });
+        self.broadcast_deck(&game, outcome.deck_exhausted);
+        if game.is_finished() {
+            self.record_game(&game);
+            self.room.broadcast(&Server::game_over(&game));
+        } else {
+            self.room.broadcast(&Server::round_started(&game));
+        }
+        let first_turn = game.turns_played() == 1;
+        drop(game);
+        self.notify_lobby();
+        if first_turn {
+            self.schedule_game_end();
+        }
+        self.bots_guess();
+    }
+
+    // Every connection in the room is asked to check the window, whichever goes first reveals
+    // the card. Timers left over from a round that already finished reveal nothing.
+    fn round_timed_out(&mut self) {
+        let closes_at = match self.room.game.borrow().round_closes_at() {
+            Some(closes_at) => closes_at,
+            None => return,
+        };
+        let now = Instant::now();
+        if now >= closes_at {
+            self.reveal_round();
+        } else if let Err(e) = self
+            .out
+            .timeout(millis(closes_at - now) + 1, ROUND_TIMEOUT)
+        {
+            error!("Failed to schedule the end of a round: {}", e);
+        }
+    }
+
+    // Everyone plays mode: no need to wait for the window if the players still guessing left
+    fn reveal_if_everyone_guessed(&mut self) {
+        if self.room.game.borrow().round_complete() {
+            self.reveal_round();
+        }
+    }
+
+    // Everyone plays mode: the bots guess as soon as a card is up
+    fn bots_guess(&mut self) {
+        if !self.room.game.borrow().everyone_plays() {
+            return;
+        }
+        let bots: Vec<(String, BotStrategy)> = self
+            .room
+            .bots
+            .borrow()
+            .iter()
+            .map(|(username, strategy)| (username.clone(), *strategy))
+            .collect();
+        for (username, strategy) in bots {
+            let guess = {
+                let game = self.room.game.borrow();
+                if game.is_finished() || game.has_guessed(&username) {
+                    continue;
+                }
+                strategy.guess(&game)
+            };
+            info!("{} guessed {:?}", username, guess);
+            self.collect_guess(&username, &guess);
+        }
     }
 
     fn remove_client(&mut self) {
         info!("Removing client...");
+        self.room
+            .spectators
+            .borrow_mut()
+            .remove(&self.out.token());
+        let mut messages = Vec::new();
         // Scope for clients & game borrow
         {
-            let mut clients = self.clients.borrow_mut();
-            let mut game = self.game.borrow_mut();
-
-            if let Some(client) = clients.get(&self.out.token()) {
-                if game.remove_player(&client.username) {
-                    let player = game.get_current_player();
-                    if let Some(p) = player {
-                        self.out
-                            .broadcast(SendableMessage::PlayerHasLeft {
-                                username: p.clone(),
-                            }).unwrap();
-                        self.out
-                            .broadcast(SendableMessage::Turn {
-                                username: p.clone(),
-                            }).unwrap();
+            let mut clients = self.room.clients.borrow_mut();
+            let mut game = self.room.game.borrow_mut();
+
+            if let Some(client) = clients.remove(&self.out.token()) {
+                // The player id only sticks to the username while that player is connected
+                self.profiles
+                    .borrow_mut()
+                    .forget_identity(&self.room.name, &client.username);
+                if clients.is_empty() {
+                    self.room.chat.borrow_mut().clear();
+                    self.room.accepted_guesses.borrow_mut().clear();
+                }
+                let changed_turn = game.remove_player(&client.username);
+                // Bots don't play on their own, so they go when the last person does
+                if clients.is_empty() {
+                    for (bot, _) in self.room.bots.borrow_mut().drain() {
+                        game.remove_player(&bot);
                     }
                 }
+                if changed_turn && !game.everyone_plays() {
+                    if let Some(p) = game.get_current_player().cloned() {
+                        messages.push(SendableMessage::PlayerHasLeft {
+                            username: p.clone(),
+                        });
+                        messages.push(Server::turn(p, &game));
+                    }
+                }
+            }
+        }
+        for message in &messages {
+            self.room.broadcast(message);
+        }
+        self.broadcast_players();
+        self.notify_lobby();
+        // Someone else has to play the bot's turn if this connection was going to
+        if let Some((_, token)) = self.room.bot_turn.get() {
+            if token == self.out.token() {
+                self.room.bot_turn.set(None);
             }
-            clients.remove(&self.out.token());
         }
-        self.broadcast_players().unwrap();
+        self.room.schedule_bot_turn();
+        self.reveal_if_everyone_guessed();
     }
 }
 
 impl Handler for Server {
+    fn on_request(&mut self, req: &Request) -> WsResult<Response> {
+        if let Err(rejection) = self.access.check(req.origin()?, req.resource()) {
+            // ws doesn't give handlers the socket address before the connection opens, so the
+            // address a proxy forwarded the request for is the only one there is to log
+            warn!(
+                "Rejected request forwarded for {}: {}",
+                req.client_addr()?.unwrap_or("no address"),
+                rejection
+            );
+            return Ok(Response::new(
+                rejection.status(),
+                rejection.reason(),
+                rejection.reason().as_bytes().to_vec(),
+            ));
+        }
+        if let Some(response) = self.room_listing(req.resource()) {
+            return Ok(response);
+        }
+        match self.history_download(req.resource()) {
+            Some(response) => {
+                info!("Sending history download {}", req.resource());
+                Ok(response)
+            }
+            None => {
+                let mut response = Response::from_request(req)?;
+                let encoding = query_param(req.resource(), "encoding");
+                let negotiated = Format::negotiate(&req.protocols()?, encoding.as_deref());
+                if let Some((format, subprotocol)) = negotiated {
+                    if let Some(subprotocol) = subprotocol {
+                        response.set_protocol(subprotocol);
+                    }
+                    self.binary_format = Some(format);
+                    self.out.set_format(format);
+                }
+                Ok(response)
+            }
+        }
+    }
+
+    fn on_open(&mut self, shake: Handshake) -> WsResult<()> {
+        if let Some(addr) = shake.peer_addr {
+            if !self.ip_connections.borrow_mut().connect(addr.ip()) {
+                warn!("Too many connections from {}, closing", addr.ip());
+                self.send_limit_exceeded(
+                    LimitKind::Connections,
+                    "Too many connections from your address",
+                    None,
+                    None,
+                );
+                return self
+                    .out
+                    .close_with_reason(CloseCode::Policy, "Too many connections");
+            }
+            self.peer_ip = Some(addr.ip());
+        }
+        self.schedule_ping()?;
+        self.schedule_sweep()
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> WsResult<Option<Frame>> {
+        // Any frame counts, so clients that only send pings aren't idle
+        self.last_activity = Instant::now();
+        if frame.opcode() == OpCode::Pong {
+            self.recieved_pong(frame.payload());
+        }
+        // The same check as the default handler
+        if frame.has_rsv1() || frame.has_rsv2() || frame.has_rsv3() {
+            return Err(ws::Error::new(
+                ws::ErrorKind::Protocol,
+                "Encountered frame with reserved bits set.",
+            ));
+        }
+        Ok(Some(frame))
+    }
+
     fn on_message(&mut self, msg: Message) -> WsResult<()> {
         debug!("Received message: {}", msg);
-        match msg {
-            Text(s) => match serde_json::from_str::<ReceivableMessage>(&s) {
-                Ok(rmsg) => self.handle_message(&rmsg),
-                _ => self.out.send(Server::unrecognised_msg()).unwrap(),
-            },
-            _ => self.out.send(Server::unrecognised_msg()).unwrap(),
+        // ws already drops connections that send much more than this, this lets clients that
+        // go just over the limit know why they were disconnected
+        if msg.len() > self.limits.max_message_size() {
+            warn!(
+                "Closing connection from {:?} after a {} byte message",
+                self.peer_ip,
+                msg.len()
+            );
+            self.send_limit_exceeded(LimitKind::MessageSize, "Message is too large", None, None);
+            return self
+                .out
+                .close_with_reason(CloseCode::Size, "Message too large");
+        }
+        let request = match msg {
+            Text(ref s) => decode_request(s),
+            Binary(ref data) => decode_binary_request(data, self.binary_format),
         };
+        // Replies go back in the format the client used, with its request id
+        if let Some(format) = request.format {
+            self.out.set_format(format);
+        }
+        self.out.set_request_id(request.request_id);
+        // Messages that can't be understood still count towards the rate limit
+        let kind = request
+            .message
+            .as_ref()
+            .map(MessageKind::from)
+            .unwrap_or(MessageKind::Other);
+        if self.check_rate_limit(kind)? {
+            self.room.touch();
+            match request.message {
+                Ok(rmsg) => self.handle_message(&rmsg),
+                Err(e) => self.send_error(e.description()),
+            }
+        }
+        self.out.set_request_id(None);
+        Ok(())
+    }
+
+    #[cfg(feature = "tls")]
+    fn upgrade_ssl_server(&mut self, sock: TcpStream) -> WsResult<SslStream<TcpStream>> {
+        match self.tls {
+            Some(ref acceptor) => acceptor.accept(sock).map_err(From::from),
+            None => Err(ws::Error::new(
+                ws::ErrorKind::Internal,
+                "Encryption requested without a certificate",
+            )),
+        }
+    }
+
+    fn on_timeout(&mut self, event: Token) -> WsResult<()> {
+        if event == SWEEP_TIMEOUT {
+            return self.sweep();
+        }
+        if event == PING_TIMEOUT {
+            return self.send_ping();
+        }
+        if event == BOT_TIMEOUT {
+            self.play_bot_turn();
+            return Ok(());
+        }
+        if event == ROUND_TIMEOUT {
+            self.round_timed_out();
+            return Ok(());
+        }
+        if event == GAME_TIMEOUT {
+            self.game_timed_out();
+            return Ok(());
+        }
+        // Ignore timeouts left over from a replay that has been replaced
+        if event.0 == self.replay_generation {
+            if let Some(step) = self.replay.pop_front() {
+                self.out.send(step.message)?;
+            }
+            self.schedule_replay_step();
+        }
         Ok(())
     }
 
@@ -216,7 +1490,7 @@ impl Handler for Server {
         // So, you may not normally want to display `reason` to the user,
         // but let's assume that we know that `reason` is human-readable.
 
-        self.remove_client();
+        self.leave();
         match code {
             CloseCode::Normal => info!("The client is done with the connection."),
             CloseCode::Away => info!("The client is leaving the site."),
@@ -259,7 +1533,6 @@ impl Handler for Server {
 //                 out.send(ReceivableMessage::Login { username: "mickjohn".to_string() });
 
 //                 move |msg| {
-//                     println!("Got message: {}", msg);
 //                     out.close(CloseCode::Normal)
 //                 }
 //             }).unwrap();
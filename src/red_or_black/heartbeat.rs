@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+// How often connections are pinged
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeartbeatSettings {
+    // Zero turns pings off
+    pub ping_interval: Duration,
+    // How many pings in a row can go unanswered before the client is treated as gone
+    pub max_missed_pongs: u32,
+}
+
+impl Default for HeartbeatSettings {
+    fn default() -> Self {
+        HeartbeatSettings {
+            ping_interval: Duration::from_secs(15),
+            max_missed_pongs: 3,
+        }
+    }
+}
+
+// Pings carry the time they were sent, so the pong says how long the round trip took
+pub fn ping_payload(now_millis: u64) -> Vec<u8> {
+    now_millis.to_be_bytes().to_vec()
+}
+
+// The pings and pongs for one connection
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    max_missed_pongs: u32,
+    // Whether a ping has been sent since the last pong
+    awaiting_pong: bool,
+    missed_pongs: u32,
+    latency_ms: Option<u64>,
+}
+
+impl Heartbeat {
+    pub fn new(max_missed_pongs: u32) -> Self {
+        Heartbeat {
+            // A client always gets at least one chance to answer
+            max_missed_pongs: max_missed_pongs.max(1),
+            awaiting_pong: false,
+            missed_pongs: 0,
+            latency_ms: None,
+        }
+    }
+
+    // Called each time a ping is due. Returns false once too many pings have gone unanswered,
+    // in which case no ping should be sent.
+    pub fn ping(&mut self) -> bool {
+        if self.awaiting_pong {
+            self.missed_pongs += 1;
+        }
+        self.awaiting_pong = true;
+        self.missed_pongs < self.max_missed_pongs
+    }
+
+    // Returns the round trip time if the pong answers one of our pings. Clients can send pongs
+    // of their own, those are ignored.
+    pub fn pong(&mut self, payload: &[u8], now_millis: u64) -> Option<u64> {
+        if payload.len() != 8 {
+            return None;
+        }
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(payload);
+        let sent = u64::from_be_bytes(bytes);
+        if sent > now_millis {
+            return None;
+        }
+        self.awaiting_pong = false;
+        self.missed_pongs = 0;
+        self.latency_ms = Some(now_millis - sent);
+        self.latency_ms
+    }
+
+    pub fn missed_pongs(&self) -> u32 {
+        self.missed_pongs
+    }
+}
+
+#[cfg(test)]
+mod pings {
+    use super::*;
+
+    #[test]
+    fn measures_latency() {
+        let mut heartbeat = Heartbeat::new(3);
+        assert!(heartbeat.ping());
+        assert_eq!(heartbeat.pong(&ping_payload(1000), 1042), Some(42));
+        assert_eq!(heartbeat.latency_ms, Some(42));
+    }
+
+    #[test]
+    fn ignores_unknown_pongs() {
+        let mut heartbeat = Heartbeat::new(3);
+        assert_eq!(heartbeat.pong(b"hello", 1000), None);
+        assert_eq!(heartbeat.pong(&ping_payload(2000), 1000), None);
+        assert_eq!(heartbeat.latency_ms, None);
+    }
+
+    #[test]
+    fn gives_up_after_missed_pongs() {
+        let mut heartbeat = Heartbeat::new(2);
+        assert!(heartbeat.ping());
+        assert!(heartbeat.ping());
+        assert_eq!(heartbeat.missed_pongs(), 1);
+        // Answering any ping resets the count
+        heartbeat.pong(&ping_payload(0), 10);
+        assert!(heartbeat.ping());
+        assert!(heartbeat.ping());
+        assert!(!heartbeat.ping());
+    }
+}
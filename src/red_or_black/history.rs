@@ -1,8 +1,9 @@
 use super::messages::CardColour;
 use deck::Card;
+use schemars::JsonSchema;
 use std::collections::VecDeque;
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct HistoryItem {
     pub username: String,
     pub guess: CardColour,
@@ -10,6 +11,11 @@ pub struct HistoryItem {
     pub card: Card,
     pub penalty: u16,
     pub turn_number: u16,
+    // When the turn was played, in milliseconds since the unix epoch
+    pub timestamp: u64,
+    // The team the player was in, when playing in teams
+    #[serde(default)]
+    pub team: Option<u8>,
 }
 
 pub struct GameHistory {
@@ -38,6 +44,32 @@ impl GameHistory {
     }
 }
 
+// How many turns a page of history has if the client doesn't ask for a number, and the most it
+// can ask for
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+// The `limit` turns before `before_turn`, or the latest turns if it isn't set, oldest first.
+// Also returns whether there are any turns before the page.
+pub fn history_page(
+    history: &[HistoryItem],
+    before_turn: Option<u16>,
+    limit: usize,
+) -> (Vec<HistoryItem>, bool) {
+    let end = match before_turn {
+        Some(turn) => history.partition_point(|item| item.turn_number < turn),
+        None => history.len(),
+    };
+    let mut start = end.saturating_sub(limit.min(MAX_PAGE_SIZE));
+    // Everyone plays mode has an item for each player on every turn, so don't split a turn
+    // between pages
+    if start < end {
+        let turn = history[start].turn_number;
+        start = history[..start].partition_point(|item| item.turn_number < turn);
+    }
+    (history[start..end].to_vec(), start > 0)
+}
+
 #[derive(Clone, Serialize)]
 pub struct CardHistory {
     size: u16,
@@ -86,6 +118,8 @@ mod game_history {
             },
             penalty: 5,
             turn_number: 1,
+            timestamp: 0,
+            team: None,
         };
         game_history.push(item);
         assert_eq!(game_history.get_history().len(), 1);
@@ -104,6 +138,8 @@ mod game_history {
             },
             penalty: 5,
             turn_number: 1,
+            timestamp: 0,
+            team: None,
         };
         game_history.push(item.clone());
         game_history.push(item.clone());
@@ -125,6 +161,8 @@ mod game_history {
             },
             penalty: 5,
             turn_number: 1,
+            timestamp: 0,
+            team: None,
         };
 
         let new_item = HistoryItem {
@@ -137,6 +175,8 @@ mod game_history {
             },
             penalty: 5,
             turn_number: 1,
+            timestamp: 0,
+            team: None,
         };
         game_history.push(old_item.clone());
         game_history.push(new_item.clone());
@@ -158,7 +198,7 @@ mod card_history {
         assert_eq!(history.get_history().len(), 3);
         let mut deck = Deck::new();
         let card = deck.pop().unwrap();
-        history.push(card.clone());
+        history.push(card);
         // Len is fixed size, should still be same
         assert_eq!(history.get_history().len(), 3);
         assert_eq!(history.get_history()[0], Some(card));
@@ -174,15 +214,86 @@ mod card_history {
         let card2 = deck.pop().unwrap();
         let card3 = deck.pop().unwrap();
         let card4 = deck.pop().unwrap();
-        history.push(card1.clone());
-        history.push(card2.clone());
-        history.push(card3.clone());
+        history.push(card1);
+        history.push(card2);
+        history.push(card3);
         assert_eq!(history.get_history()[0], Some(card3));
         assert_eq!(history.get_history()[1], Some(card2));
         assert_eq!(history.get_history()[2], Some(card1));
-        history.push(card4.clone());
+        history.push(card4);
         assert_eq!(history.get_history()[0], Some(card4));
         assert_eq!(history.get_history()[1], Some(card3));
         assert_eq!(history.get_history()[2], Some(card2));
     }
 }
+
+#[cfg(test)]
+mod pages {
+    use super::*;
+    use deck::{Suit, Value};
+
+    fn history(turns: u16) -> Vec<HistoryItem> {
+        (1..=turns)
+            .map(|turn_number| HistoryItem {
+                username: "Jimmy".to_string(),
+                guess: CardColour::Red,
+                outcome: true,
+                card: Card {
+                    value: Value::Ace,
+                    suit: Suit::Club,
+                },
+                penalty: 5,
+                turn_number,
+                timestamp: 0,
+                team: None,
+            })
+            .collect()
+    }
+
+    fn turns(page: &[HistoryItem]) -> Vec<u16> {
+        page.iter().map(|item| item.turn_number).collect()
+    }
+
+    #[test]
+    fn latest_page() {
+        let (page, has_more) = history_page(&history(10), None, 3);
+        assert_eq!(turns(&page), vec![8, 9, 10]);
+        assert!(has_more);
+        let (page, has_more) = history_page(&history(2), None, 3);
+        assert_eq!(turns(&page), vec![1, 2]);
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn pages_before_a_turn() {
+        let history = history(10);
+        let (page, has_more) = history_page(&history, Some(8), 3);
+        assert_eq!(turns(&page), vec![5, 6, 7]);
+        assert!(has_more);
+        let (page, has_more) = history_page(&history, Some(3), 3);
+        assert_eq!(turns(&page), vec![1, 2]);
+        assert!(!has_more);
+        assert!(history_page(&history, Some(1), 3).0.is_empty());
+    }
+
+    #[test]
+    fn turns_are_not_split_between_pages() {
+        // Three players guess every card
+        let history: Vec<HistoryItem> = history(4)
+            .into_iter()
+            .flat_map(|item| vec![item.clone(), item.clone(), item])
+            .collect();
+        let (page, has_more) = history_page(&history, None, 4);
+        assert_eq!(turns(&page), vec![3, 3, 3, 4, 4, 4]);
+        assert!(has_more);
+        let (page, has_more) = history_page(&history, Some(3), 4);
+        assert_eq!(turns(&page), vec![1, 1, 1, 2, 2, 2]);
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn pages_are_capped() {
+        let (page, _) = history_page(&history(200), None, 1000);
+        assert_eq!(page.len(), MAX_PAGE_SIZE);
+    }
+}
@@ -0,0 +1,304 @@
+use super::messages::ReceivableMessage;
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// A token bucket: up to `burst` messages can be sent at once, then `per_second` after that
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+// Parsed from "<burst>/<per second>", e.g. "10/2"
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid rate limit '{}', expected <burst>/<per second>", s);
+        let mut parts = s.splitn(2, '/');
+        let burst = parts
+            .next()
+            .and_then(|b| b.trim().parse::<u32>().ok())
+            .filter(|b| *b > 0)
+            .ok_or_else(invalid)?;
+        let per_second = parts
+            .next()
+            .and_then(|p| p.trim().parse::<f64>().ok())
+            .filter(|p| *p > 0.0)
+            .ok_or_else(invalid)?;
+        Ok(RateLimit { burst, per_second })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitSettings {
+    // The biggest message a client can send, in bytes
+    pub max_message_size: usize,
+    pub max_connections_per_ip: usize,
+    // How many limits a client can break before they are disconnected
+    pub max_violations: u32,
+    pub login: RateLimit,
+    pub guess: RateLimit,
+    pub chat: RateLimit,
+    pub other: RateLimit,
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        LimitSettings {
+            max_message_size: 4096,
+            max_connections_per_ip: 10,
+            max_violations: 20,
+            login: RateLimit {
+                burst: 3,
+                per_second: 0.2,
+            },
+            guess: RateLimit {
+                burst: 5,
+                per_second: 2.0,
+            },
+            chat: RateLimit {
+                burst: 10,
+                per_second: 1.0,
+            },
+            other: RateLimit {
+                burst: 10,
+                per_second: 2.0,
+            },
+        }
+    }
+}
+
+// The groups of messages that have their own rate limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+pub enum MessageKind {
+    Login,
+    Guess,
+    Chat,
+    Other,
+}
+
+impl From<&ReceivableMessage> for MessageKind {
+    fn from(msg: &ReceivableMessage) -> MessageKind {
+        match *msg {
+            ReceivableMessage::Login { .. } => MessageKind::Login,
+            ReceivableMessage::Guess { .. } => MessageKind::Guess,
+            ReceivableMessage::Chat { .. } | ReceivableMessage::React { .. } => MessageKind::Chat,
+            _ => MessageKind::Other,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket {
+            limit,
+            tokens: f64::from(limit.burst),
+            last_refill: now,
+        }
+    }
+
+    // Take a token, or say how long until there will be one
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.duration_since(self.last_refill);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second).min(f64::from(self.limit.burst));
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait_secs = (1.0 - self.tokens) / self.limit.per_second;
+            Err(Duration::from_millis((wait_secs * 1000.0).ceil() as u64))
+        }
+    }
+}
+
+// Which limit a client broke
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum LimitKind {
+    RateLimit,
+    MessageSize,
+    Connections,
+}
+
+// The limits for one connection
+#[derive(Clone, Debug)]
+pub struct ConnectionLimits {
+    max_message_size: usize,
+    max_violations: u32,
+    violations: u32,
+    buckets: HashMap<MessageKind, TokenBucket>,
+}
+
+impl ConnectionLimits {
+    pub fn new(settings: &LimitSettings) -> Self {
+        let now = Instant::now();
+        let mut buckets = HashMap::new();
+        buckets.insert(MessageKind::Login, TokenBucket::new(settings.login, now));
+        buckets.insert(MessageKind::Guess, TokenBucket::new(settings.guess, now));
+        buckets.insert(MessageKind::Chat, TokenBucket::new(settings.chat, now));
+        buckets.insert(MessageKind::Other, TokenBucket::new(settings.other, now));
+        ConnectionLimits {
+            max_message_size: settings.max_message_size,
+            max_violations: settings.max_violations,
+            violations: 0,
+            buckets,
+        }
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    // Ok if the message can be handled, otherwise how long until it could be
+    pub fn check_rate(&mut self, kind: MessageKind, now: Instant) -> Result<(), Duration> {
+        self.buckets
+            .get_mut(&kind)
+            .expect("every message kind has a bucket")
+            .take(now)
+    }
+
+    // Count a broken limit, returns true once the client has broken too many
+    pub fn add_violation(&mut self) -> bool {
+        self.violations += 1;
+        self.violations > self.max_violations
+    }
+}
+
+// How many connections are open from each IP address
+pub struct IpConnections {
+    max_per_ip: usize,
+    open: HashMap<IpAddr, usize>,
+}
+
+impl IpConnections {
+    pub fn new(max_per_ip: usize) -> Self {
+        IpConnections {
+            max_per_ip,
+            open: HashMap::new(),
+        }
+    }
+
+    // Returns false if the address already has too many connections open
+    pub fn connect(&mut self, ip: IpAddr) -> bool {
+        let open = self.open.entry(ip).or_insert(0);
+        if *open >= self.max_per_ip {
+            return false;
+        }
+        *open += 1;
+        true
+    }
+
+    pub fn disconnect(&mut self, ip: IpAddr) {
+        let closed = match self.open.get_mut(&ip) {
+            Some(open) => {
+                *open -= 1;
+                *open == 0
+            }
+            None => false,
+        };
+        if closed {
+            self.open.remove(&ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod connection_limits {
+    use super::*;
+
+    fn settings() -> LimitSettings {
+        LimitSettings {
+            max_violations: 2,
+            guess: RateLimit {
+                burst: 2,
+                per_second: 1.0,
+            },
+            ..LimitSettings::default()
+        }
+    }
+
+    #[test]
+    fn parse_rate_limit() {
+        assert_eq!(
+            "10/2.5".parse::<RateLimit>(),
+            Ok(RateLimit {
+                burst: 10,
+                per_second: 2.5,
+            })
+        );
+        assert!("10".parse::<RateLimit>().is_err());
+        assert!("0/1".parse::<RateLimit>().is_err());
+        assert!("5/0".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn bursts_then_refills() {
+        let mut limits = ConnectionLimits::new(&settings());
+        let now = Instant::now();
+        assert!(limits.check_rate(MessageKind::Guess, now).is_ok());
+        assert!(limits.check_rate(MessageKind::Guess, now).is_ok());
+        assert_eq!(
+            limits.check_rate(MessageKind::Guess, now),
+            Err(Duration::from_secs(1))
+        );
+        // Other kinds of message have their own bucket
+        assert!(limits.check_rate(MessageKind::Chat, now).is_ok());
+
+        let later = now + Duration::from_millis(1500);
+        assert!(limits.check_rate(MessageKind::Guess, later).is_ok());
+        assert_eq!(
+            limits.check_rate(MessageKind::Guess, later),
+            Err(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn repeat_offenders() {
+        let mut limits = ConnectionLimits::new(&settings());
+        assert!(!limits.add_violation());
+        assert!(!limits.add_violation());
+        assert!(limits.add_violation());
+    }
+
+    #[test]
+    fn message_kinds() {
+        use red_or_black::messages::CardColour;
+        let guess = ReceivableMessage::Guess {
+            card_colour: CardColour::Red,
+            guess_id: None,
+            turn_number: None,
+        };
+        assert_eq!(MessageKind::from(&guess), MessageKind::Guess);
+        assert_eq!(
+            MessageKind::from(&ReceivableMessage::ListReplays),
+            MessageKind::Other
+        );
+    }
+
+    #[test]
+    fn connections_per_ip() {
+        let mut connections = IpConnections::new(2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(connections.connect(ip));
+        assert!(connections.connect(ip));
+        assert!(!connections.connect(ip));
+        assert!(connections.connect(other));
+        connections.disconnect(ip);
+        assert!(connections.connect(ip));
+    }
+}
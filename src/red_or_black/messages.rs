@@ -1,23 +1,169 @@
+use super::bots::BotStrategy;
+use super::chat::ChatMessage;
+use super::export::ExportFormat;
 use super::history::HistoryItem;
+use super::limits::{LimitKind, MessageKind};
+use super::profiles::{LeaderboardEntry, PlayerProfile};
+use super::room::RoomSummary;
+use super::rules::{DeckExhausted, PenaltyShare};
+use super::settings::GameSettings;
+use super::stats::{PlayerStats, TeamStats};
 use deck;
 use deck::Card;
+use schemars::JsonSchema;
 use serde_json;
 use std::collections::VecDeque;
 use ws::Message;
 
-#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize, JsonSchema)]
 pub enum CardColour {
     Red,
     Black,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// Why a guess wasn't played
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum NackReason {
+    NotLoggedIn,
+    // Logged in to the room but not one of the players in its game
+    NotPlaying,
+    NotYourTurn,
+    // The guess was for a turn that has already been played, or hasn't come yet
+    WrongTurn,
+    GameOver,
+    // Everyone plays mode only allows one guess per player for each card
+    AlreadyGuessed,
+}
+
+impl NackReason {
+    pub fn description(self) -> &'static str {
+        match self {
+            NackReason::NotLoggedIn => "You need to log in to guess",
+            NackReason::NotPlaying => "You aren't playing in this game",
+            NackReason::NotYourTurn => "It's not your turn",
+            NackReason::WrongTurn => "That turn isn't being played",
+            NackReason::GameOver => "The game is over",
+            NackReason::AlreadyGuessed => "You've already guessed this card",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub enum ReceivableMessage {
-    Login { username: String },
-    Guess { card_colour: CardColour },
+    Login {
+        username: String,
+        // An id chosen by the client, used to keep track of the player across games
+        #[serde(default)]
+        player_id: Option<String>,
+        // The token sent back in PlayerToken the first time `player_id` was used
+        #[serde(default)]
+        player_token: Option<String>,
+        // The room to join, players go to the default room if this isn't set
+        #[serde(default)]
+        room: Option<String>,
+        // The password or invite code for the room, if it needs one
+        #[serde(default)]
+        code: Option<String>,
+    },
+    // `guess_id` is chosen by the client and sent back in the Ack or Nack. If `turn_number` is
+    // set the guess is only played on that turn, so a guess sent twice isn't played twice.
+    Guess {
+        card_colour: CardColour,
+        #[serde(default)]
+        guess_id: Option<String>,
+        #[serde(default)]
+        turn_number: Option<u16>,
+    },
+    // Get the global leaderboard, or the leaderboard for a room
+    GetLeaderboard {
+        room: Option<String>,
+    },
+    GetProfile {
+        player_id: String,
+    },
+    // Get the full history of the current game as a file
+    ExportHistory {
+        format: ExportFormat,
+    },
+    ListReplays,
+    // Replay a finished game. `speed` defaults to 1.0, i.e. the speed it was played at.
+    Replay {
+        game_id: String,
+        speed: Option<f64>,
+    },
+    Chat {
+        text: String,
+    },
+    // React to the card drawn on a turn
+    React {
+        emoji: String,
+        turn_number: u16,
+    },
+    // Create a room and become its host. If `invite_only` is set players need an invite code
+    // to join. `settings` defaults to the server's game settings.
+    CreateRoom {
+        room: String,
+        password: Option<String>,
+        #[serde(default)]
+        invite_only: bool,
+        // Private rooms aren't shown in the room list
+        #[serde(default)]
+        private: bool,
+        settings: Option<GameSettings>,
+    },
+    // Replace the invite code of the room, only the host can do this
+    RotateInviteCode,
+    // Watch the game in a room without taking a seat
+    Spectate {
+        room: String,
+        #[serde(default)]
+        code: Option<String>,
+    },
+    // Get the public rooms
+    ListRooms,
+    // Get the public rooms now and whenever they change, until unsubscribed
+    SubscribeLobby,
+    UnsubscribeLobby,
+    // Give a seat in the room to a bot, only the host can do this. The bot is named after its
+    // strategy if it isn't given a name.
+    AddBot {
+        strategy: BotStrategy,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    RemoveBot {
+        username: String,
+    },
+    // Move a player to another team, only the host can do this. Players are put in the
+    // smallest team when they join.
+    AssignTeam {
+        username: String,
+        team: u8,
+    },
+    // Get a page of the game's history, the turns before `before_turn` or the latest ones.
+    // `limit` defaults to 20 turns and can be up to 100.
+    GetHistory {
+        #[serde(default)]
+        before_turn: Option<u16>,
+        #[serde(default)]
+        limit: Option<u16>,
+    },
+}
+
+// More about each player in a Players message
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PlayerInfo {
+    pub username: String,
+    // The round trip time of the player's last ping, if it has answered one
+    pub latency_ms: Option<u64>,
+    // Bots are played by the server
+    #[serde(default)]
+    pub bot: bool,
+    #[serde(default)]
+    pub team: Option<u8>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(tag = "msg_type")]
 pub enum SendableMessage {
     Ok {
@@ -25,20 +171,37 @@ pub enum SendableMessage {
     },
     Players {
         players: Vec<String>,
+        #[serde(default)]
+        details: Vec<PlayerInfo>,
     },
     Turn {
         username: String,
+        // The number of the turn being played, for guesses to target
+        #[serde(default)]
+        turn_number: u16,
+        #[serde(default)]
+        team: Option<u8>,
     },
     Error {
         error: String,
     },
     LoggedIn,
+    // Sent the first time a player id logs in. Later logins with the id have to send the token.
+    PlayerToken {
+        player_id: String,
+        token: String,
+    },
     GuessResult {
         correct: bool,
         card: deck::Card,
         penalty: u16,
         username: String,
         guess: CardColour,
+        #[serde(default)]
+        team: Option<u8>,
+        // Who drinks for a wrong guess when playing in teams, following the house rule
+        #[serde(default)]
+        shares: Vec<PenaltyShare>,
     },
     Penalty {
         penalty: u16,
@@ -57,12 +220,115 @@ pub enum SendableMessage {
     RequestHistory {
         history: VecDeque<Option<Card>>,
     },
-    GameHistory {
-        history: Vec<HistoryItem>,
+    // A page of the game's history, oldest first. Sent with the latest turns when a player
+    // joins, and for GetHistory. `has_more` is set if there are turns before the page.
+    HistoryPage {
+        items: Vec<HistoryItem>,
+        has_more: bool,
+    },
+    // Everyone plays mode: a new card is up and everyone can guess it. The `window_ms` to guess
+    // starts with the first guess.
+    RoundStarted {
+        turn_number: u16,
+        window_ms: u64,
+        waiting_for: Vec<String>,
+    },
+    // Everyone plays mode: a player has locked in their guess, which is kept secret until the
+    // card is revealed. The card is revealed in `closes_in_ms` if anyone is still guessing.
+    GuessReceived {
+        username: String,
+        turn_number: u16,
+        waiting_for: Vec<String>,
+        closes_in_ms: u64,
+    },
+    // Everyone plays mode: the card was revealed. Everyone in `guesses` that got it wrong takes
+    // `penalty`, and the penalty only goes up if `everyone_right`.
+    RoundResult {
+        turn_number: u16,
+        card: Card,
+        penalty: u16,
+        everyone_right: bool,
+        guesses: Vec<HistoryItem>,
+        // Who drinks for the wrong guesses when playing in teams, following the house rule
+        #[serde(default)]
+        shares: Vec<PenaltyShare>,
+    },
+    // A turn was added to the end of the history
+    HistoryAppended(HistoryItem),
+    // A card was added to the front of the card history, and the oldest one dropped
+    CardHistoryShifted {
+        card: Card,
     },
     CardsLeft {
         cards_left: usize,
     },
+    DeckStats(deck::DeckStats),
+    DeckExhausted(DeckExhausted),
+    GameOver {
+        game_id: String,
+        turns_played: usize,
+        players: Vec<PlayerStats>,
+        #[serde(default)]
+        teams: Vec<TeamStats>,
+    },
+    // The score for each team, sent after every turn when playing in teams
+    TeamScores {
+        teams: Vec<TeamStats>,
+    },
+    Leaderboard {
+        room: Option<String>,
+        entries: Vec<LeaderboardEntry>,
+    },
+    Profile(PlayerProfile),
+    HistoryExport {
+        format: ExportFormat,
+        data: String,
+    },
+    Replays {
+        game_ids: Vec<String>,
+    },
+    Chat(ChatMessage),
+    Reaction {
+        username: String,
+        emoji: String,
+        turn_number: u16,
+    },
+    // The most recent chat messages, sent when a player joins
+    ChatHistory {
+        messages: Vec<ChatMessage>,
+    },
+    RoomCreated {
+        room: String,
+        invite_code: Option<String>,
+    },
+    Rooms {
+        rooms: Vec<RoomSummary>,
+    },
+    // Sent to the host when the invite code changes
+    InviteCode {
+        room: String,
+        invite_code: String,
+    },
+    // A guess was played, sent to the player that made it before the result
+    Ack {
+        guess_id: Option<String>,
+        turn_number: u16,
+    },
+    // A guess wasn't played. `turn_number` is the turn the game is on.
+    Nack {
+        guess_id: Option<String>,
+        turn_number: u16,
+        reason: NackReason,
+        error: String,
+    },
+    // The client broke one of the server's limits. `message_kind` and `retry_after_ms` are only
+    // set for rate limits.
+    LimitExceeded {
+        limit: LimitKind,
+        error: String,
+        message_kind: Option<MessageKind>,
+        retry_after_ms: Option<u64>,
+    },
 }
 
 impl From<SendableMessage> for Message {
@@ -71,7 +337,7 @@ impl From<SendableMessage> for Message {
     }
 }
 
-impl<'a> From<&'a SendableMessage> for Message {
+impl From<&SendableMessage> for Message {
     fn from(s: &SendableMessage) -> Message {
         Message::text(serde_json::to_string(s).unwrap())
     }
@@ -85,8 +351,8 @@ impl From<ReceivableMessage> for Message {
 }
 
 #[cfg(test)]
-impl<'a> From<&'a ReceivableMessage> for Message {
-    fn from(s: &'a ReceivableMessage) -> Message {
+impl From<&ReceivableMessage> for Message {
+    fn from(s: &ReceivableMessage) -> Message {
         Message::text(serde_json::to_string(s).unwrap())
     }
 }
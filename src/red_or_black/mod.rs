@@ -1,24 +1,176 @@
+// ws::Error is large, but it's the error type ws handlers have to return and the helpers that
+// wrap a ws::Sender pass it straight through, so it isn't worth boxing
+#![allow(clippy::result_large_err)]
+
+pub mod access;
+pub mod bots;
+pub mod chat;
+mod events;
+mod export;
 mod game;
+pub mod heartbeat;
 mod history;
-mod messages;
-mod rules;
+pub mod limits;
+pub mod messages;
+mod profiles;
+pub mod protocol;
+mod replay;
+mod room;
+pub mod rules;
+pub mod schema;
+pub mod settings;
+pub mod simulation;
+mod stats;
+pub mod sweeper;
+pub mod terminal;
+mod timeouts;
+#[cfg(feature = "tls")]
+mod tls;
 
 // pub use self::rules::HistoryItem;
 
 use self::game::Server;
+use self::heartbeat::Heartbeat;
+use self::limits::{ConnectionLimits, IpConnections};
+use self::profiles::ProfileStore;
+use self::protocol::ClientSender;
+use self::room::Rooms;
 use self::rules::RedOrBlack;
+use self::settings::GameSettings;
+use self::sweeper::Sweeper;
+use config::Config;
+#[cfg(feature = "tls")]
+use openssl::ssl::SslAcceptor;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::rc::Rc;
-use ws::listen;
-
-pub fn start_server(ip_and_port: &str) {
-    let game = Rc::new(RefCell::new(RedOrBlack::new(Vec::new())));
-    let clients = Rc::new(RefCell::new(HashMap::new()));
-    info!("Starting up on {}", ip_and_port);
-    listen(ip_and_port, |out| Server {
-        out,
-        game: game.clone(),
-        clients: clients.clone(),
-    }).unwrap()
+use std::time::Instant;
+use ws::{Builder, Settings};
+
+// The most a websocket frame header can take: 2 bytes, 8 for the length and 4 for the mask
+const MAX_FRAME_HEADER: usize = 14;
+// Clients have no need to split a message into more frames than this
+const MAX_FRAGMENTS: usize = 4;
+
+pub fn start_server(config: Config) {
+    #[cfg(feature = "tls")]
+    let tls = load_tls(config.tls_cert, config.tls_key).map(Rc::new);
+    #[cfg(feature = "tls")]
+    let encrypt_server = tls.is_some();
+    #[cfg(not(feature = "tls"))]
+    let encrypt_server = check_tls_disabled(&config.tls_cert, &config.tls_key);
+
+    let replay_dir = config.replay_dir;
+    let reconnect_grace = config.reconnect_grace;
+    let game = match replay_dir {
+        Some(ref dir) => {
+            info!("Recording games to {}", dir.display());
+            restore_game(config.game_settings.clone(), dir)
+        }
+        None => RedOrBlack::with_settings(Vec::new(), config.game_settings.clone()),
+    };
+    let rooms = Rooms::new(
+        game,
+        config.game_settings,
+        config.chat_settings,
+        replay_dir.clone(),
+        config.max_rooms,
+    );
+    let main_room = rooms.main();
+    let rooms = Rc::new(RefCell::new(rooms));
+    let profiles = Rc::new(RefCell::new(ProfileStore::new(config.profiles_file)));
+    let access = Rc::new(config.access_settings);
+    let heartbeat_settings = Rc::new(config.heartbeat_settings);
+    let sweeper = Rc::new(RefCell::new(Sweeper::new(config.sweeper_settings)));
+    let limit_settings = config.limit_settings;
+    let ip_connections = Rc::new(RefCell::new(IpConnections::new(
+        limit_settings.max_connections_per_ip,
+    )));
+    info!("Starting up on {}", config.ip_and_port);
+    let settings = Settings {
+        encrypt_server,
+        // Don't buffer more than a message's worth of data for a connection. Frames that can't
+        // fit close the connection before they are read, and so do messages split into too many
+        // frames. ws 0.7 has no limit on the size of a single frame, the buffer stands in for it.
+        in_buffer_capacity: limit_settings.max_message_size + MAX_FRAME_HEADER + 1,
+        in_buffer_grow: false,
+        fragments_capacity: MAX_FRAGMENTS,
+        fragments_grow: false,
+        ..Settings::default()
+    };
+    let factory = |out| Server {
+        out: ClientSender::new(out),
+        rooms: rooms.clone(),
+        room: main_room.clone(),
+        profiles: profiles.clone(),
+        replay_dir: replay_dir.clone(),
+        replay: VecDeque::new(),
+        replay_generation: 0,
+        reconnect_grace,
+        limits: ConnectionLimits::new(&limit_settings),
+        ip_connections: ip_connections.clone(),
+        peer_ip: None,
+        access: access.clone(),
+        sweeper: sweeper.clone(),
+        last_activity: Instant::now(),
+        heartbeat_settings: heartbeat_settings.clone(),
+        heartbeat: Heartbeat::new(heartbeat_settings.max_missed_pongs),
+        heartbeat_latency: None,
+        left: false,
+        binary_format: None,
+        #[cfg(feature = "tls")]
+        tls: tls.clone(),
+    };
+    Builder::new()
+        .with_settings(settings)
+        .build(factory)
+        .unwrap()
+        .listen(config.ip_and_port.as_str())
+        .unwrap();
+}
+
+// Load the certificate and key, the server won't start if they are set but can't be used
+#[cfg(feature = "tls")]
+fn load_tls(cert: Option<PathBuf>, key: Option<PathBuf>) -> Option<SslAcceptor> {
+    match (cert, key) {
+        (Some(cert), Some(key)) => match tls::acceptor(&cert, &key) {
+            Ok(acceptor) => {
+                info!("Encrypting connections with {}", cert.display());
+                Some(acceptor)
+            }
+            Err(e) => {
+                error!("Failed to load the TLS certificate and key: {}", e);
+                process::exit(1);
+            }
+        },
+        (None, None) => None,
+        _ => {
+            error!("RED_OR_BLACK_TLS_CERT and RED_OR_BLACK_TLS_KEY must be set together");
+            process::exit(1);
+        }
+    }
+}
+
+// Don't quietly serve unencrypted connections when TLS was asked for
+#[cfg(not(feature = "tls"))]
+fn check_tls_disabled(cert: &Option<PathBuf>, key: &Option<PathBuf>) -> bool {
+    if cert.is_some() || key.is_some() {
+        error!("TLS is configured but the server was built without the tls feature");
+        process::exit(1);
+    }
+    false
+}
+
+// Pick up the game that was being played when the server stopped, or start a new one
+fn restore_game(settings: GameSettings, dir: &Path) -> RedOrBlack {
+    match RedOrBlack::restore(settings.clone(), dir.to_path_buf()) {
+        Ok(Some(game)) => return game,
+        Ok(None) => info!("No game to restore, starting a new one"),
+        Err(e) => error!("Failed to restore game, starting a new one: {}", e),
+    }
+    let mut game = RedOrBlack::with_settings(Vec::new(), settings);
+    game.record_events(dir.to_path_buf());
+    game
 }
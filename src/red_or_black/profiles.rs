@@ -0,0 +1,359 @@
+use super::access::secrets_match;
+use super::stats::PlayerStats;
+use rand::distributions::Alphanumeric;
+use rand::{self, Rng};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const PLAYER_TOKEN_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum ProfileError {
+    TokenRequired,
+    WrongToken,
+}
+
+impl ProfileError {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            ProfileError::TokenRequired => "That player id needs the player token it was given",
+            ProfileError::WrongToken => "Wrong player token for that player id",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct ProfileTotals {
+    pub games_played: u32,
+    pub turns: u32,
+    pub correct: u32,
+    pub accuracy: f64,
+    pub penalty_served: u32,
+    pub best_streak: u32,
+}
+
+impl ProfileTotals {
+    fn add_game(&mut self, stats: &PlayerStats) {
+        self.games_played += 1;
+        self.turns += stats.turns;
+        self.correct += stats.correct;
+        self.penalty_served += stats.penalty_served;
+        if stats.longest_streak > self.best_streak {
+            self.best_streak = stats.longest_streak;
+        }
+        if self.turns > 0 {
+            self.accuracy = f64::from(self.correct) * 100.0 / f64::from(self.turns);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PlayerProfile {
+    pub player_id: String,
+    // The last username this player played as
+    pub username: String,
+    pub totals: ProfileTotals,
+    pub rooms: HashMap<String, ProfileTotals>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct LeaderboardEntry {
+    pub username: String,
+    pub games_played: u32,
+    pub accuracy: f64,
+    pub penalty_served: u32,
+    pub best_streak: u32,
+}
+
+// Lifetime stats for players across games. If a path is given the profiles are loaded from and
+// saved to a JSON file there, otherwise they only last as long as the server.
+pub struct ProfileStore {
+    path: Option<PathBuf>,
+    profiles: HashMap<String, PlayerProfile>,
+    // The secret token handed out the first time each player id logged in. Kept apart from the
+    // profiles so it's never sent with them.
+    tokens: HashMap<String, String>,
+    // The player id used by each username, keyed by the room they're playing in
+    identities: HashMap<(String, String), String>,
+}
+
+impl ProfileStore {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let profiles = match path {
+            Some(ref p) if p.exists() => match Self::load(p) {
+                Ok(profiles) => profiles,
+                Err(e) => {
+                    error!("Failed to load profiles from {}: {}", p.display(), e);
+                    HashMap::new()
+                }
+            },
+            _ => HashMap::new(),
+        };
+        info!("Loaded {} player profiles", profiles.len());
+        let tokens = match path.as_ref().map(|p| Self::tokens_path(p)) {
+            Some(ref p) if p.exists() => match Self::load(p) {
+                Ok(tokens) => tokens,
+                Err(e) => {
+                    error!("Failed to load player tokens from {}: {}", p.display(), e);
+                    HashMap::new()
+                }
+            },
+            _ => HashMap::new(),
+        };
+
+        ProfileStore {
+            path,
+            profiles,
+            tokens,
+            identities: HashMap::new(),
+        }
+    }
+
+    fn tokens_path(path: &Path) -> PathBuf {
+        path.with_extension("tokens")
+    }
+
+    fn load<T: DeserializeOwned>(path: &PathBuf) -> io::Result<T> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    // Write to a temporary file first so a crash can't leave a half written file behind
+    fn write<T: Serialize>(path: &PathBuf, value: &T) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(value)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(ref path) = self.path {
+            Self::write(path, &self.profiles)?;
+        }
+        Ok(())
+    }
+
+    fn save_tokens(&self) -> io::Result<()> {
+        if let Some(ref path) = self.path {
+            Self::write(&Self::tokens_path(path), &self.tokens)?;
+        }
+        Ok(())
+    }
+
+    // Player ids that haven't been given a token yet are free for anyone to use
+    pub fn check_token(&self, player_id: &str, token: Option<&str>) -> Result<(), ProfileError> {
+        match self.tokens.get(player_id) {
+            None => Ok(()),
+            Some(expected) => match token {
+                None => Err(ProfileError::TokenRequired),
+                Some(token) if secrets_match(token, expected) => Ok(()),
+                Some(_) => Err(ProfileError::WrongToken),
+            },
+        }
+    }
+
+    // Give the player id a token the first time it's used, later logins have to send it back
+    pub fn issue_token(&mut self, player_id: &str) -> Option<String> {
+        if self.tokens.contains_key(player_id) {
+            return None;
+        }
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(PLAYER_TOKEN_LENGTH)
+            .collect();
+        self.tokens.insert(player_id.to_string(), token.clone());
+        if let Err(e) = self.save_tokens() {
+            error!("Failed to save player tokens: {}", e);
+        }
+        Some(token)
+    }
+
+    // Players that don't send an id are tracked by their username instead
+    fn player_id_for(&self, room: &str, username: &str) -> String {
+        self.identities
+            .get(&(room.to_string(), username.to_string()))
+            .cloned()
+            .unwrap_or_else(|| format!("name:{}", username))
+    }
+
+    pub fn identify(&mut self, room: &str, username: &str, player_id: &str) {
+        self.identities
+            .insert((room.to_string(), username.to_string()), player_id.to_string());
+    }
+
+    // Forget a player's id when they leave, anyone with the same name in other rooms keeps theirs
+    pub fn forget_identity(&mut self, room: &str, username: &str) {
+        self.identities
+            .remove(&(room.to_string(), username.to_string()));
+    }
+
+    // Add the stats from a finished game to each players profile
+    pub fn record_game(&mut self, room: &str, stats: &[PlayerStats]) {
+        for player_stats in stats {
+            let player_id = self.player_id_for(room, &player_stats.username);
+            let profile = self
+                .profiles
+                .entry(player_id.clone())
+                .or_insert_with(|| PlayerProfile {
+                    player_id,
+                    username: player_stats.username.clone(),
+                    totals: ProfileTotals::default(),
+                    rooms: HashMap::new(),
+                });
+            profile.username = player_stats.username.clone();
+            profile.totals.add_game(player_stats);
+            profile
+                .rooms
+                .entry(room.to_string())
+                .or_default()
+                .add_game(player_stats);
+        }
+
+        if let Err(e) = self.save() {
+            error!("Failed to save profiles: {}", e);
+        }
+    }
+
+    pub fn get_profile(&self, player_id: &str) -> Option<&PlayerProfile> {
+        self.profiles.get(player_id)
+    }
+
+    // Players ranked by accuracy, then by games played. If a room is given only games played in
+    // that room count.
+    pub fn leaderboard(&self, room: Option<&str>, limit: usize) -> Vec<LeaderboardEntry> {
+        let mut entries: Vec<LeaderboardEntry> = self
+            .profiles
+            .values()
+            .filter_map(|profile| {
+                let totals = match room {
+                    Some(room) => profile.rooms.get(room)?,
+                    None => &profile.totals,
+                };
+                Some(LeaderboardEntry {
+                    username: profile.username.clone(),
+                    games_played: totals.games_played,
+                    accuracy: totals.accuracy,
+                    penalty_served: totals.penalty_served,
+                    best_streak: totals.best_streak,
+                })
+            })
+            .collect();
+
+        entries.sort_by(|a, b| {
+            b.accuracy
+                .partial_cmp(&a.accuracy)
+                .unwrap()
+                .then(b.games_played.cmp(&a.games_played))
+        });
+        entries.truncate(limit);
+        entries
+    }
+}
+
+#[cfg(test)]
+mod profile_store {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn stats(username: &str, turns: u32, correct: u32, longest_streak: u32) -> PlayerStats {
+        PlayerStats {
+            username: username.to_string(),
+            turns,
+            correct,
+            correct_percentage: f64::from(correct) * 100.0 / f64::from(turns),
+            longest_streak,
+            penalty_served: (turns - correct) * 5,
+            biggest_penalty: 5,
+            red_guesses: turns,
+            black_guesses: 0,
+        }
+    }
+
+    #[test]
+    fn games_are_added_up() {
+        let mut store = ProfileStore::new(None);
+        store.identify("main", "mick", "abc");
+        store.record_game("main", &[stats("mick", 10, 5, 3)]);
+        store.record_game("main", &[stats("mick", 10, 10, 10)]);
+
+        let profile = store.get_profile("abc").unwrap();
+        assert_eq!(profile.totals.games_played, 2);
+        assert_eq!(profile.totals.turns, 20);
+        assert_eq!(profile.totals.accuracy, 75.0);
+        assert_eq!(profile.totals.penalty_served, 25);
+        assert_eq!(profile.totals.best_streak, 10);
+        assert_eq!(profile.rooms["main"], profile.totals);
+    }
+
+    #[test]
+    fn only_the_leaving_players_identity_is_forgotten() {
+        let mut store = ProfileStore::new(None);
+        store.identify("main", "mick", "abc");
+        store.identify("other", "mick", "def");
+        // Mick left the main room, the mick in the other room is still playing
+        store.forget_identity("main", "mick");
+        store.record_game("main", &[stats("mick", 4, 1, 1)]);
+        store.record_game("other", &[stats("mick", 4, 2, 2)]);
+
+        assert!(store.get_profile("abc").is_none());
+        assert!(store.get_profile("name:mick").is_some());
+        assert!(store.get_profile("def").is_some());
+    }
+
+    #[test]
+    fn players_without_an_id_use_their_username() {
+        let mut store = ProfileStore::new(None);
+        store.record_game("main", &[stats("john", 4, 1, 1)]);
+        assert!(store.get_profile("name:john").is_some());
+    }
+
+    #[test]
+    fn leaderboard_is_sorted_by_accuracy() {
+        let mut store = ProfileStore::new(None);
+        store.record_game("main", &[stats("mick", 10, 5, 3), stats("john", 10, 8, 4)]);
+        store.record_game("other", &[stats("begbie", 10, 9, 9)]);
+
+        let leaderboard = store.leaderboard(None, 10);
+        let names: Vec<&str> = leaderboard.iter().map(|e| e.username.as_str()).collect();
+        assert_eq!(names, vec!["begbie", "john", "mick"]);
+
+        let leaderboard = store.leaderboard(Some("main"), 1);
+        assert_eq!(leaderboard.len(), 1);
+        assert_eq!(leaderboard[0].username, "john");
+    }
+
+    #[test]
+    fn player_ids_need_their_token() {
+        let mut store = ProfileStore::new(None);
+        assert_eq!(store.check_token("abc", None), Ok(()));
+        let token = store.issue_token("abc").unwrap();
+        assert_eq!(store.issue_token("abc"), None);
+
+        assert_eq!(store.check_token("abc", Some(&token)), Ok(()));
+        assert_eq!(store.check_token("abc", None), Err(ProfileError::TokenRequired));
+        assert_eq!(store.check_token("abc", Some("guess")), Err(ProfileError::WrongToken));
+    }
+
+    #[test]
+    fn profiles_are_saved_and_loaded() {
+        let path = env::temp_dir().join(format!("red_or_black_profiles_{}.json", process::id()));
+        {
+            let mut store = ProfileStore::new(Some(path.clone()));
+            store.identify("main", "mick", "abc");
+            store.record_game("main", &[stats("mick", 10, 5, 3)]);
+            store.issue_token("abc").unwrap();
+        }
+
+        let store = ProfileStore::new(Some(path.clone()));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(ProfileStore::tokens_path(&path)).unwrap();
+        assert_eq!(store.get_profile("abc").unwrap().totals.games_played, 1);
+        assert_eq!(store.check_token("abc", None), Err(ProfileError::TokenRequired));
+    }
+}
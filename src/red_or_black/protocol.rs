@@ -0,0 +1,452 @@
+use super::messages::{ReceivableMessage, SendableMessage};
+use ciborium;
+use rmp_serde;
+use schemars::JsonSchema;
+use serde::de::Error;
+use serde_json::{self, Map, Value};
+use std::borrow::{Borrow, Cow};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use ws::util::Token;
+use ws::{CloseCode, Message, Result as WsResult, Sender};
+
+// The version of the envelope, bumped when a message changes in a way old clients can't handle
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// SendableMessage is tagged with this in the legacy format
+const LEGACY_TAG: &str = "msg_type";
+
+// How a client sends its messages, and so how it is sent them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // Bare messages, tagged differently in each direction. Still accepted, but deprecated.
+    Legacy,
+    Envelope,
+    // Envelopes in binary frames, asked for when the client connects
+    MessagePack,
+    Cbor,
+}
+
+// The subprotocols for the binary formats, e.g. `Sec-WebSocket-Protocol: red-or-black.msgpack`
+const SUBPROTOCOLS: [(&str, Format); 2] = [
+    ("red-or-black.msgpack", Format::MessagePack),
+    ("red-or-black.cbor", Format::Cbor),
+];
+
+impl Format {
+    // The binary format a client asked for, from the subprotocols it offered or the `encoding`
+    // query parameter, along with the subprotocol to accept if it used one
+    pub fn negotiate(
+        protocols: &[&str],
+        encoding: Option<&str>,
+    ) -> Option<(Format, Option<&'static str>)> {
+        for protocol in protocols {
+            if let Some(&(name, format)) = SUBPROTOCOLS.iter().find(|s| s.0 == *protocol) {
+                return Some((format, Some(name)));
+            }
+        }
+        match encoding?.to_lowercase().as_str() {
+            "msgpack" | "messagepack" => Some((Format::MessagePack, None)),
+            "cbor" => Some((Format::Cbor, None)),
+            _ => None,
+        }
+    }
+}
+
+// Every message is wrapped in one of these, whichever way it's going. `request_id` is chosen by
+// the client and echoed on everything sent to it while the server handles that request.
+// `payload` holds the fields of the message, and is left out for messages without any.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct Envelope {
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+}
+
+impl Envelope {
+    fn new(message_type: String, payload: Option<Value>, request_id: Option<String>) -> Self {
+        Envelope {
+            message_type,
+            version: PROTOCOL_VERSION,
+            request_id,
+            payload,
+        }
+    }
+
+    // ReceivableMessage is externally tagged, e.g. {"Guess": {...}}, or just "ListRooms"
+    pub fn from_receivable(message: &ReceivableMessage, request_id: Option<String>) -> Self {
+        match serde_json::to_value(message).unwrap() {
+            Value::Object(tagged) => {
+                let (message_type, payload) = tagged.into_iter().next().unwrap();
+                Envelope::new(message_type, Some(payload), request_id)
+            }
+            Value::String(message_type) => Envelope::new(message_type, None, request_id),
+            other => panic!("ReceivableMessage serialized as {}", other),
+        }
+    }
+
+    pub fn to_receivable(&self) -> serde_json::Result<ReceivableMessage> {
+        let tagged = match self.payload {
+            Some(ref payload) => {
+                let mut tagged = Map::new();
+                tagged.insert(self.message_type.clone(), payload.clone());
+                Value::Object(tagged)
+            }
+            None => Value::String(self.message_type.clone()),
+        };
+        serde_json::from_value(tagged)
+    }
+
+    // SendableMessage has its tag next to its fields, e.g. {"msg_type": "Turn", "username": ...}
+    pub fn from_sendable(message: &SendableMessage, request_id: Option<String>) -> Self {
+        let mut fields = match serde_json::to_value(message).unwrap() {
+            Value::Object(fields) => fields,
+            other => panic!("SendableMessage serialized as {}", other),
+        };
+        let message_type = match fields.remove(LEGACY_TAG) {
+            Some(Value::String(message_type)) => message_type,
+            _ => panic!("SendableMessage serialized without its {}", LEGACY_TAG),
+        };
+        let payload = if fields.is_empty() {
+            None
+        } else {
+            Some(Value::Object(fields))
+        };
+        Envelope::new(message_type, payload, request_id)
+    }
+
+    pub fn to_sendable(&self) -> serde_json::Result<SendableMessage> {
+        let mut fields = match self.payload {
+            Some(Value::Object(ref fields)) => fields.clone(),
+            Some(_) => return Err(serde_json::Error::custom("the payload must be an object")),
+            None => Map::new(),
+        };
+        fields.insert(
+            LEGACY_TAG.to_string(),
+            Value::String(self.message_type.clone()),
+        );
+        serde_json::from_value(Value::Object(fields))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    Unrecognised,
+    UnsupportedVersion,
+}
+
+impl ProtocolError {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            ProtocolError::Unrecognised => "Unrecognised message",
+            ProtocolError::UnsupportedVersion => "Unsupported protocol version",
+        }
+    }
+}
+
+// A message from a client. `format` is None if it couldn't be told what the client was sending.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub format: Option<Format>,
+    pub request_id: Option<String>,
+    pub message: Result<ReceivableMessage, ProtocolError>,
+}
+
+fn unrecognised(format: Option<Format>) -> Request {
+    Request {
+        format,
+        request_id: None,
+        message: Err(ProtocolError::Unrecognised),
+    }
+}
+
+// Envelopes are told apart by their type field, which no legacy message has
+pub fn decode_request(text: &str) -> Request {
+    match serde_json::from_str(text) {
+        Ok(value) => decode_value(value, Format::Envelope),
+        Err(_) => unrecognised(None),
+    }
+}
+
+// Binary messages are always envelopes, in the format the client asked for when it connected
+pub fn decode_binary_request(data: &[u8], format: Option<Format>) -> Request {
+    match format.and_then(|f| binary_value(data, f)) {
+        Some(ref value) if value.get("type").is_some() => {
+            decode_value(value.clone(), format.unwrap())
+        }
+        _ => unrecognised(format),
+    }
+}
+
+fn binary_value(data: &[u8], format: Format) -> Option<Value> {
+    match format {
+        Format::MessagePack => rmp_serde::from_slice(data).ok(),
+        Format::Cbor => ciborium::de::from_reader(data).ok(),
+        Format::Legacy | Format::Envelope => None,
+    }
+}
+
+fn decode_value(value: Value, envelope_format: Format) -> Request {
+    if value.get("type").is_none() {
+        return Request {
+            format: Some(Format::Legacy),
+            request_id: None,
+            message: serde_json::from_value(value).map_err(|_| ProtocolError::Unrecognised),
+        };
+    }
+    // Keep the request id even if the rest is wrong, so the error can be matched to the request
+    let request_id = value
+        .get("request_id")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let message = match serde_json::from_value::<Envelope>(value) {
+        Ok(ref envelope) if envelope.version == 0 || envelope.version > PROTOCOL_VERSION => {
+            Err(ProtocolError::UnsupportedVersion)
+        }
+        Ok(envelope) => envelope
+            .to_receivable()
+            .map_err(|_| ProtocolError::Unrecognised),
+        Err(_) => Err(ProtocolError::Unrecognised),
+    };
+    Request {
+        format: Some(envelope_format),
+        request_id,
+        message,
+    }
+}
+
+// Legacy clients aren't sent request ids, they have nowhere to go
+pub fn encode(message: &SendableMessage, format: Format, request_id: Option<String>) -> Message {
+    if format == Format::Legacy {
+        return Message::text(serde_json::to_string(message).unwrap());
+    }
+    let envelope = Envelope::from_sendable(message, request_id);
+    match format {
+        Format::MessagePack => Message::binary(rmp_serde::to_vec_named(&envelope).unwrap()),
+        Format::Cbor => {
+            let mut data = Vec::new();
+            ciborium::ser::into_writer(&envelope, &mut data).unwrap();
+            Message::binary(data)
+        }
+        _ => Message::text(serde_json::to_string(&envelope).unwrap()),
+    }
+}
+
+// Read a message from the server in either format, for clients
+pub fn decode_sendable(text: &str) -> serde_json::Result<SendableMessage> {
+    let value: Value = serde_json::from_str(text)?;
+    if value.get(LEGACY_TAG).is_some() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value::<Envelope>(value)?.to_sendable()
+    }
+}
+
+pub fn decode_binary_sendable(data: &[u8], format: Format) -> Option<SendableMessage> {
+    let value = binary_value(data, format)?;
+    serde_json::from_value::<Envelope>(value)
+        .ok()?
+        .to_sendable()
+        .ok()
+}
+
+// Sends messages to a connection in the format it last used, echoing the id of the request being
+// handled. Clones share the format and request id, so rooms and the lobby can keep one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientSender {
+    sender: Sender,
+    format: Rc<Cell<Format>>,
+    request_id: Rc<RefCell<Option<String>>>,
+}
+
+impl ClientSender {
+    pub fn new(sender: Sender) -> Self {
+        ClientSender {
+            sender,
+            format: Rc::new(Cell::new(Format::Legacy)),
+            request_id: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format.get()
+    }
+
+    pub fn set_format(&self, format: Format) {
+        self.format.set(format);
+    }
+
+    pub fn set_request_id(&self, request_id: Option<String>) {
+        *self.request_id.borrow_mut() = request_id;
+    }
+
+    pub fn send<M: Borrow<SendableMessage>>(&self, message: M) -> WsResult<()> {
+        let request_id = RefCell::borrow(&self.request_id).clone();
+        self.sender
+            .send(encode(message.borrow(), self.format.get(), request_id))
+    }
+
+    pub fn token(&self) -> Token {
+        self.sender.token()
+    }
+
+    pub fn timeout(&self, ms: u64, token: Token) -> WsResult<()> {
+        self.sender.timeout(ms, token)
+    }
+
+    pub fn ping(&self, data: Vec<u8>) -> WsResult<()> {
+        self.sender.ping(data)
+    }
+
+    pub fn close(&self, code: CloseCode) -> WsResult<()> {
+        self.sender.close(code)
+    }
+
+    pub fn close_with_reason<S: Into<Cow<'static, str>>>(
+        &self,
+        code: CloseCode,
+        reason: S,
+    ) -> WsResult<()> {
+        self.sender.close_with_reason(code, reason)
+    }
+}
+
+#[cfg(test)]
+mod envelopes {
+    use super::*;
+    use red_or_black::messages::CardColour;
+
+    fn guess() -> ReceivableMessage {
+        ReceivableMessage::Guess {
+            card_colour: CardColour::Red,
+            guess_id: None,
+            turn_number: None,
+        }
+    }
+
+    #[test]
+    fn legacy_messages_are_still_accepted() {
+        let request = decode_request(r#"{"Guess": {"card_colour": "Red"}}"#);
+        assert_eq!(request.format, Some(Format::Legacy));
+        assert_eq!(request.message, Ok(guess()));
+        assert_eq!(
+            decode_request("\"ListRooms\"").message,
+            Ok(ReceivableMessage::ListRooms)
+        );
+    }
+
+    #[test]
+    fn envelopes_are_accepted() {
+        let request = decode_request(
+            r#"{"type": "Guess", "version": 1, "request_id": "7", "payload": {"card_colour": "Red"}}"#,
+        );
+        assert_eq!(request.format, Some(Format::Envelope));
+        assert_eq!(request.request_id, Some("7".to_string()));
+        assert_eq!(request.message, Ok(guess()));
+        let request = decode_request(r#"{"type": "ListRooms", "version": 1}"#);
+        assert_eq!(request.message, Ok(ReceivableMessage::ListRooms));
+        assert_eq!(request.request_id, None);
+    }
+
+    #[test]
+    fn bad_envelopes_keep_their_request_id() {
+        let request = decode_request(r#"{"type": "Guess", "version": 2, "request_id": "a"}"#);
+        assert_eq!(request.message, Err(ProtocolError::UnsupportedVersion));
+        assert_eq!(request.request_id, Some("a".to_string()));
+        let request = decode_request(r#"{"type": "Dance", "version": 1, "request_id": "b"}"#);
+        assert_eq!(request.message, Err(ProtocolError::Unrecognised));
+        assert_eq!(request.request_id, Some("b".to_string()));
+        assert_eq!(decode_request("not json").format, None);
+    }
+
+    #[test]
+    fn server_messages_in_both_formats() {
+        let message = SendableMessage::Turn {
+            username: "amy".to_string(),
+            turn_number: 2,
+            team: Some(1),
+        };
+        let legacy = encode(&message, Format::Legacy, Some("1".to_string()));
+        assert_eq!(
+            legacy.as_text().unwrap(),
+            r#"{"msg_type":"Turn","username":"amy","turn_number":2,"team":1}"#
+        );
+        let enveloped = encode(&message, Format::Envelope, Some("1".to_string()));
+        assert_eq!(
+            enveloped.as_text().unwrap(),
+            r#"{"type":"Turn","version":1,"request_id":"1","payload":{"team":1,"turn_number":2,"username":"amy"}}"#
+        );
+        let logged_in = encode(&SendableMessage::LoggedIn, Format::Envelope, None);
+        assert_eq!(
+            logged_in.as_text().unwrap(),
+            r#"{"type":"LoggedIn","version":1}"#
+        );
+        for text in &[legacy.as_text().unwrap(), enveloped.as_text().unwrap()] {
+            match decode_sendable(text).unwrap() {
+                SendableMessage::Turn { username, .. } => assert_eq!(username, "amy"),
+                other => panic!("Expected a turn, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+        for message in &[guess(), ReceivableMessage::ListRooms] {
+            let envelope = Envelope::from_receivable(message, None);
+            assert_eq!(envelope.to_receivable().unwrap(), *message);
+        }
+    }
+
+    #[test]
+    fn binary_formats_are_negotiated() {
+        assert_eq!(
+            Format::negotiate(&["chat", "red-or-black.cbor"], Some("msgpack")),
+            Some((Format::Cbor, Some("red-or-black.cbor")))
+        );
+        assert_eq!(
+            Format::negotiate(&[], Some("MsgPack")),
+            Some((Format::MessagePack, None))
+        );
+        assert_eq!(Format::negotiate(&["chat"], Some("xml")), None);
+    }
+
+    #[test]
+    fn binary_messages_round_trip() {
+        let message = SendableMessage::Turn {
+            username: "amy".to_string(),
+            turn_number: 2,
+            team: Some(1),
+        };
+        for format in &[Format::MessagePack, Format::Cbor] {
+            let encoded = encode(&message, *format, Some("3".to_string()));
+            assert!(encoded.is_binary());
+            match decode_binary_sendable(&encoded.into_data(), *format) {
+                Some(SendableMessage::Turn { username, .. }) => assert_eq!(username, "amy"),
+                other => panic!("Expected a turn, got {:?}", other),
+            }
+
+            let envelope = Envelope::from_receivable(&guess(), Some("4".to_string()));
+            let data = match *format {
+                Format::MessagePack => rmp_serde::to_vec_named(&envelope).unwrap(),
+                _ => {
+                    let mut data = Vec::new();
+                    ciborium::ser::into_writer(&envelope, &mut data).unwrap();
+                    data
+                }
+            };
+            let request = decode_binary_request(&data, Some(*format));
+            assert_eq!(request.format, Some(*format));
+            assert_eq!(request.request_id, Some("4".to_string()));
+            assert_eq!(request.message, Ok(guess()));
+        }
+        // Binary messages can't be read without a format
+        assert_eq!(
+            decode_binary_request(&[0x80], None).message,
+            Err(ProtocolError::Unrecognised)
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use super::events::{GameEvent, LoggedEvent};
+use super::history::HistoryItem;
+use super::messages::{PlayerInfo, SendableMessage};
+use super::rules;
+use super::stats;
+use std::collections::{HashMap, VecDeque};
+
+// The longest we wait between two messages in a replay, so that nobody has to sit through the
+// quiet bits of a game
+const MAX_REPLAY_GAP_MS: u64 = 5000;
+
+// A message to send during a replay, and how long to wait after the previous one before sending
+#[derive(Clone, Debug)]
+pub struct ReplayStep {
+    pub delay_ms: u64,
+    pub message: SendableMessage,
+}
+
+// The players message at this point in the game. Latency isn't recorded, so replays don't have it
+fn players_message(players: &[String], teams: &HashMap<String, u8>) -> SendableMessage {
+    SendableMessage::Players {
+        players: players.to_vec(),
+        details: players
+            .iter()
+            .map(|p| PlayerInfo {
+                username: p.clone(),
+                latency_ms: None,
+                bot: false,
+                team: teams.get(p).cloned(),
+            }).collect(),
+    }
+}
+
+// The players in each team, for team games
+fn team_players(players: &[String], teams: &HashMap<String, u8>) -> Vec<Vec<String>> {
+    let count = teams.values().cloned().max().unwrap_or(0);
+    (1..=count)
+        .map(|team| {
+            players
+                .iter()
+                .filter(|p| teams.get(*p) == Some(&team))
+                .cloned()
+                .collect()
+        }).collect()
+}
+
+// Turn the event log of a game back into the messages that were sent to the players during it.
+// `speed` is a multiplier, e.g. 2.0 replays the game twice as fast as it was played.
+pub fn replay_steps(game_id: &str, events: &[LoggedEvent], speed: f64) -> VecDeque<ReplayStep> {
+    let mut steps = VecDeque::new();
+    let mut players: Vec<String> = Vec::new();
+    let mut teams: HashMap<String, u8> = HashMap::new();
+    let mut history: Vec<HistoryItem> = Vec::new();
+    let mut previous_timestamp = events.first().map(|e| e.timestamp).unwrap_or(0);
+
+    for logged in events {
+        let gap = logged.timestamp.saturating_sub(previous_timestamp);
+        let mut delay_ms = (gap.min(MAX_REPLAY_GAP_MS) as f64 / speed) as u64;
+        previous_timestamp = logged.timestamp;
+
+        let mut messages = Vec::new();
+        match logged.event {
+            GameEvent::DeckShuffled {
+                ref cards,
+                ref reason,
+            } => {
+                if let Some(ref deck_exhausted) = *reason {
+                    messages.push(SendableMessage::DeckExhausted(deck_exhausted.clone()));
+                }
+                messages.push(SendableMessage::CardsLeft {
+                    cards_left: cards.len(),
+                });
+            }
+            GameEvent::PlayerJoined { ref username } => {
+                players.push(username.clone());
+                messages.push(players_message(&players, &teams));
+            }
+            GameEvent::PlayerLeft { ref username } => {
+                players.retain(|p| p != username);
+                teams.remove(username);
+                messages.push(SendableMessage::PlayerHasLeft {
+                    username: username.clone(),
+                });
+                messages.push(players_message(&players, &teams));
+            }
+            GameEvent::TeamAssigned {
+                ref username,
+                team,
+            } => {
+                teams.insert(username.clone(), team);
+                messages.push(players_message(&players, &teams));
+            }
+            GameEvent::TurnPlayed {
+                ref item,
+                ref next_player,
+                cards_left,
+            } => {
+                history.push(item.clone());
+                messages.push(SendableMessage::GuessResult {
+                    correct: item.outcome,
+                    card: item.card,
+                    penalty: item.penalty,
+                    username: item.username.clone(),
+                    guess: item.guess.clone(),
+                    team: item.team,
+                    // The teams' house rule isn't logged
+                    shares: Vec::new(),
+                });
+                messages.push(SendableMessage::HistoryAppended(item.clone()));
+                messages.push(SendableMessage::CardHistoryShifted { card: item.card });
+                messages.push(SendableMessage::CardsLeft { cards_left });
+                if let Some(ref next_player) = *next_player {
+                    messages.push(SendableMessage::Turn {
+                        username: next_player.clone(),
+                        turn_number: item.turn_number + 1,
+                        team: teams.get(next_player).cloned(),
+                    });
+                }
+            }
+            GameEvent::RoundPlayed {
+                ref items,
+                cards_left,
+            } => {
+                let first = match items.first() {
+                    Some(first) => first,
+                    None => continue,
+                };
+                history.extend(items.iter().cloned());
+                // When everyone was right the items have the penalty after it went up
+                let everyone_right = rules::everyone_right(&players, items);
+                messages.push(SendableMessage::RoundResult {
+                    turn_number: first.turn_number,
+                    card: first.card,
+                    penalty: if everyone_right {
+                        first.penalty - 5
+                    } else {
+                        first.penalty
+                    },
+                    everyone_right,
+                    guesses: items.clone(),
+                    // The teams' house rule isn't logged
+                    shares: Vec::new(),
+                });
+                for item in items {
+                    messages.push(SendableMessage::HistoryAppended(item.clone()));
+                }
+                messages.push(SendableMessage::CardHistoryShifted { card: first.card });
+                messages.push(SendableMessage::CardsLeft { cards_left });
+            }
+            GameEvent::GameEnded => {
+                messages.push(SendableMessage::GameOver {
+                    game_id: game_id.to_string(),
+                    turns_played: history.last().map_or(0, |i| usize::from(i.turn_number)),
+                    players: stats::player_stats(&history),
+                    teams: stats::team_stats(&history, &team_players(&players, &teams)),
+                });
+            }
+            GameEvent::GameReset => {}
+        }
+
+        // Messages from the same event go out together
+        for message in messages {
+            steps.push_back(ReplayStep { delay_ms, message });
+            delay_ms = 0;
+        }
+    }
+    steps
+}
+
+#[cfg(test)]
+mod replay_steps {
+    use super::*;
+    use deck::{Card, Suit, Value};
+    use red_or_black::messages::CardColour;
+
+    fn logged(timestamp: u64, event: GameEvent) -> LoggedEvent {
+        LoggedEvent { timestamp, event }
+    }
+
+    fn events() -> Vec<LoggedEvent> {
+        vec![
+            logged(
+                1000,
+                GameEvent::PlayerJoined {
+                    username: "mick".to_string(),
+                },
+            ),
+            logged(
+                3000,
+                GameEvent::TurnPlayed {
+                    item: HistoryItem {
+                        username: "mick".to_string(),
+                        guess: CardColour::Red,
+                        outcome: false,
+                        card: Card {
+                            value: Value::Six,
+                            suit: Suit::Club,
+                        },
+                        penalty: 5,
+                        turn_number: 1,
+                        timestamp: 3000,
+                        team: None,
+                    },
+                    next_player: Some("mick".to_string()),
+                    cards_left: 51,
+                },
+            ),
+            logged(60_000, GameEvent::GameEnded),
+        ]
+    }
+
+    #[test]
+    fn events_become_messages() {
+        let steps = replay_steps("abc", &events(), 1.0);
+        let types: Vec<String> = steps
+            .iter()
+            .map(|s| {
+                format!("{:?}", s.message)
+                    .split(&[' ', '('][..])
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                "Players",
+                "GuessResult",
+                "HistoryAppended",
+                "CardHistoryShifted",
+                "CardsLeft",
+                "Turn",
+                "GameOver"
+            ]
+        );
+    }
+
+    #[test]
+    fn rounds_are_replayed_together() {
+        let card = Card {
+            value: Value::Two,
+            suit: Suit::Heart,
+        };
+        let item = |username: &str, guess: CardColour| HistoryItem {
+            username: username.to_string(),
+            outcome: guess == CardColour::Red,
+            guess,
+            card,
+            penalty: 15,
+            turn_number: 4,
+            timestamp: 1000,
+            team: None,
+        };
+        let events = vec![
+            logged(
+                1000,
+                GameEvent::RoundPlayed {
+                    items: vec![item("mick", CardColour::Red), item("john", CardColour::Red)],
+                    cards_left: 40,
+                },
+            ),
+            logged(2000, GameEvent::GameEnded),
+        ];
+        let steps = replay_steps("abc", &events, 1.0);
+        match steps[0].message {
+            SendableMessage::RoundResult {
+                penalty,
+                everyone_right,
+                ref guesses,
+                ..
+            } => {
+                assert_eq!(penalty, 10);
+                assert!(everyone_right);
+                assert_eq!(guesses.len(), 2);
+            }
+            ref m => panic!("Expected a round result, got {:?}", m),
+        }
+        assert_eq!(steps.len(), 6);
+        match steps[5].message {
+            SendableMessage::GameOver { turns_played, .. } => assert_eq!(turns_played, 4),
+            ref m => panic!("Expected game over, got {:?}", m),
+        }
+    }
+
+    #[test]
+    fn delays_follow_the_game() {
+        let delays: Vec<u64> = replay_steps("abc", &events(), 1.0)
+            .iter()
+            .map(|s| s.delay_ms)
+            .collect();
+        // The long wait before the game ended is capped
+        assert_eq!(delays, vec![0, 2000, 0, 0, 0, 0, MAX_REPLAY_GAP_MS]);
+    }
+
+    #[test]
+    fn speed_scales_delays() {
+        let delays: Vec<u64> = replay_steps("abc", &events(), 4.0)
+            .iter()
+            .map(|s| s.delay_ms)
+            .collect();
+        assert_eq!(delays, vec![0, 500, 0, 0, 0, 0, MAX_REPLAY_GAP_MS / 4]);
+    }
+}
@@ -0,0 +1,666 @@
+use super::access::secrets_match;
+use super::bots::{think_time_ms, BotStrategy};
+use super::chat::{Chat, ChatSettings};
+use super::messages::{PlayerInfo, SendableMessage};
+use super::protocol::ClientSender;
+use super::rules::RedOrBlack;
+use super::settings::GameSettings;
+use super::timeouts::{BOT_TIMEOUT, ROUND_TIMEOUT};
+use rand::{self, Rng};
+use schemars::JsonSchema;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use ws::util::Token;
+
+// The room players join if they don't ask for one, it always exists and is open to everyone
+pub const DEFAULT_ROOM: &str = "main";
+
+const MAX_ROOM_NAME_LENGTH: usize = 32;
+
+const INVITE_CODE_LENGTH: usize = 4;
+
+// Letters and numbers that can't be mistaken for each other, e.g. no O and 0
+const INVITE_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Debug, PartialEq)]
+pub enum RoomError {
+    NoSuchRoom,
+    CodeRequired,
+    WrongCode,
+    AlreadyExists,
+    InvalidName,
+    NotHost,
+    TooManyBots,
+    NameTaken,
+    NoSuchBot,
+    TooManyRooms,
+}
+
+impl RoomError {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            RoomError::NoSuchRoom => "No room with that name",
+            RoomError::CodeRequired => "That room needs a password or invite code",
+            RoomError::WrongCode => "Wrong password or invite code",
+            RoomError::AlreadyExists => "There is already a room with that name",
+            RoomError::InvalidName => {
+                "Room names must be 1 to 32 letters, numbers, dashes or underscores"
+            }
+            RoomError::NotHost => "Only the host of the room can do that",
+            RoomError::TooManyBots => "The room already has as many bots as it can",
+            RoomError::NameTaken => "Someone in the room already has that name",
+            RoomError::NoSuchBot => "No bot with that name",
+            RoomError::TooManyRooms => "The server already has as many rooms as it can",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Client {
+    pub username: String,
+    pub token: usize,
+    // Used to send the client messages meant for everyone in the room
+    pub sender: ClientSender,
+    // The round trip time of the client's last ping
+    pub latency_ms: Option<u64>,
+}
+
+pub fn new_invite_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..INVITE_CODE_LENGTH)
+        .map(|_| INVITE_CODE_CHARS[rng.gen_range(0, INVITE_CODE_CHARS.len())] as char)
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum RoomState {
+    // Nobody has played a turn yet
+    Lobby,
+    InProgress,
+    Finished,
+}
+
+// What the lobby shows about a room
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct RoomSummary {
+    pub name: String,
+    pub players: usize,
+    pub spectators: usize,
+    pub settings: GameSettings,
+    pub state: RoomState,
+    pub password_required: bool,
+}
+
+// Who can get into a room, and who runs it
+#[derive(Debug, Default)]
+pub struct RoomAccess {
+    pub password: Option<String>,
+    pub invite_code: Option<String>,
+    // The connection of the player that runs the room
+    pub host: Option<Token>,
+}
+
+impl RoomAccess {
+    // Players get in with either the password or the current invite code
+    pub fn check(&self, code: Option<&str>) -> Result<(), RoomError> {
+        if self.password.is_none() && self.invite_code.is_none() {
+            return Ok(());
+        }
+        let code = code.ok_or(RoomError::CodeRequired)?;
+        let password_ok = self
+            .password
+            .as_ref()
+            .map(|p| secrets_match(code, p))
+            .unwrap_or(false);
+        let invite_ok = self
+            .invite_code
+            .as_ref()
+            .map(|c| secrets_match(&code.trim().to_uppercase(), c))
+            .unwrap_or(false);
+        if password_ok || invite_ok {
+            Ok(())
+        } else {
+            Err(RoomError::WrongCode)
+        }
+    }
+
+    // Replace the invite code, so the old one can't be used any more
+    pub fn rotate_invite_code(&mut self, token: Token) -> Result<String, RoomError> {
+        if self.host != Some(token) {
+            return Err(RoomError::NotHost);
+        }
+        let code = new_invite_code();
+        self.invite_code = Some(code.clone());
+        Ok(code)
+    }
+}
+
+// Everything for one game. The handles are shared by every connection in the room.
+#[derive(Clone)]
+pub struct Room {
+    pub name: String,
+    pub game: Rc<RefCell<RedOrBlack>>,
+    pub clients: Rc<RefCell<HashMap<Token, Client>>>,
+    pub chat: Rc<RefCell<Chat>>,
+    pub access: Rc<RefCell<RoomAccess>>,
+    // Connections watching the game without playing
+    pub spectators: Rc<RefCell<HashMap<Token, ClientSender>>>,
+    // Private rooms aren't shown in the lobby
+    pub private: bool,
+    // When anyone in the room last sent a message
+    pub last_active: Rc<Cell<Instant>>,
+    // The bots playing in the room and how they guess, by username
+    pub bots: Rc<RefCell<HashMap<String, BotStrategy>>>,
+    // The turn number a bot's guess has been scheduled for, and the connection whose timeout
+    // will play it
+    pub bot_turn: Rc<Cell<Option<(u16, Token)>>>,
+    // The id of each player's last guess that was played and the turn it was played on, kept
+    // here rather than on the connection so a guess sent again after a reconnect is recognised
+    pub accepted_guesses: Rc<RefCell<HashMap<String, (String, u16)>>>,
+}
+
+impl Room {
+    pub fn new(name: &str, game: RedOrBlack, chat_settings: ChatSettings) -> Self {
+        Room {
+            name: name.to_string(),
+            game: Rc::new(RefCell::new(game)),
+            clients: Rc::new(RefCell::new(HashMap::new())),
+            chat: Rc::new(RefCell::new(Chat::new(chat_settings))),
+            access: Rc::new(RefCell::new(RoomAccess::default())),
+            spectators: Rc::new(RefCell::new(HashMap::new())),
+            private: false,
+            last_active: Rc::new(Cell::new(Instant::now())),
+            bots: Rc::new(RefCell::new(HashMap::new())),
+            bot_turn: Rc::new(Cell::new(None)),
+            accepted_guesses: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    pub fn touch(&self) {
+        self.last_active.set(Instant::now());
+    }
+
+    pub fn is_idle(&self, idle: Duration, now: Instant) -> bool {
+        now.duration_since(self.last_active.get()) >= idle
+    }
+
+    // Send a message to every player and spectator in the room
+    pub fn broadcast(&self, message: &SendableMessage) {
+        for client in self.clients.borrow().values() {
+            if let Err(e) = client.sender.send(message) {
+                error!("Failed to send to {}: {}", client.username, e);
+            }
+        }
+        for sender in self.spectators.borrow().values() {
+            if let Err(e) = sender.send(message) {
+                error!("Failed to send to spectator: {}", e);
+            }
+        }
+    }
+
+    // The players in the room, with their latency, followed by the bots
+    pub fn players_message(&self) -> SendableMessage {
+        let game = self.game.borrow();
+        let mut details: Vec<PlayerInfo> = self
+            .clients
+            .borrow()
+            .values()
+            .map(|c| PlayerInfo {
+                username: c.username.clone(),
+                latency_ms: c.latency_ms,
+                bot: false,
+                team: game.team_of(&c.username),
+            }).collect();
+        let mut bots: Vec<String> = self.bots.borrow().keys().cloned().collect();
+        bots.sort();
+        details.extend(bots.into_iter().map(|username| PlayerInfo {
+            team: game.team_of(&username),
+            username,
+            latency_ms: None,
+            bot: true,
+        }));
+        SendableMessage::Players {
+            players: details.iter().map(|p| p.username.clone()).collect(),
+            details,
+        }
+    }
+
+    pub fn is_host(&self, token: Token) -> bool {
+        self.access.borrow().host == Some(token)
+    }
+
+    // The strategy of the bot whose turn it is, if it's a bot's turn
+    pub fn bot_to_play(&self) -> Option<(String, BotStrategy)> {
+        let mut game = self.game.borrow_mut();
+        // In everyone plays mode the bots guess as soon as the card is up instead
+        if game.is_finished() || game.everyone_plays() {
+            return None;
+        }
+        let username = game.get_current_player()?.clone();
+        let strategy = *self.bots.borrow().get(&username)?;
+        Some((username, strategy))
+    }
+
+    // If it's a bot's turn, have one of the players' connections play it after a pause. Bots
+    // can only play while there are people in the room.
+    pub fn schedule_bot_turn(&self) {
+        if self.bot_to_play().is_none() {
+            return;
+        }
+        let turn = self.game.borrow().turn_number();
+        if let Some((scheduled, _)) = self.bot_turn.get() {
+            if scheduled == turn {
+                return;
+            }
+        }
+        let sender = self
+            .clients
+            .borrow()
+            .values()
+            .map(|c| c.sender.clone())
+            .min_by_key(|s| s.token());
+        if let Some(sender) = sender {
+            self.bot_turn.set(Some((turn, sender.token())));
+            if let Err(e) = sender.timeout(think_time_ms(), BOT_TIMEOUT) {
+                error!("Failed to schedule a bot's turn: {}", e);
+                self.bot_turn.set(None);
+            }
+        }
+    }
+
+    // Ask every player's connection to check the guess window in `ms`, so the card is still
+    // revealed if some of them leave before then
+    pub fn schedule_round_reveal(&self, ms: u64) {
+        for client in self.clients.borrow().values() {
+            if let Err(e) = client.sender.timeout(ms, ROUND_TIMEOUT) {
+                error!("Failed to schedule the end of a round: {}", e);
+            }
+        }
+    }
+
+    pub fn summary(&self) -> RoomSummary {
+        let game = self.game.borrow();
+        let state = if game.is_finished() {
+            RoomState::Finished
+        } else if game.get_full_history().is_empty() {
+            RoomState::Lobby
+        } else {
+            RoomState::InProgress
+        };
+        let access = self.access.borrow();
+        RoomSummary {
+            name: self.name.clone(),
+            players: self.clients.borrow().len(),
+            spectators: self.spectators.borrow().len(),
+            settings: game.settings().clone(),
+            state,
+            password_required: access.password.is_some() || access.invite_code.is_some(),
+        }
+    }
+
+    // If the host has gone, hand the room over to someone else that's still in it
+    pub fn replace_host(&self, leaving: Token) {
+        let mut access = self.access.borrow_mut();
+        if access.host == Some(leaving) {
+            access.host = self
+                .clients
+                .borrow()
+                .keys()
+                .filter(|t| **t != leaving)
+                .min()
+                .cloned();
+        }
+    }
+}
+
+// Games in the default room are recorded straight into the replay directory, other rooms get
+// their own directory so that restoring the default room only sees its own games
+pub fn room_replay_dir(replay_dir: &Path, room: &str) -> PathBuf {
+    if room == DEFAULT_ROOM {
+        replay_dir.to_path_buf()
+    } else {
+        replay_dir.join("rooms").join(room)
+    }
+}
+
+fn is_valid_room_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_ROOM_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub struct Rooms {
+    rooms: HashMap<String, Room>,
+    default_settings: GameSettings,
+    chat_settings: ChatSettings,
+    // Games in new rooms are recorded here if it is set
+    replay_dir: Option<PathBuf>,
+    // The most rooms that can be open at once, counting the default room. 0 means no limit.
+    max_rooms: usize,
+    // Connections that are sent the room list whenever it changes
+    lobby: HashMap<Token, ClientSender>,
+    // The last room list sent to the lobby, so it's only sent when something changes
+    last_listing: Vec<RoomSummary>,
+}
+
+impl Rooms {
+    // `main_game` is the game for the default room, which may have been restored
+    pub fn new(
+        main_game: RedOrBlack,
+        default_settings: GameSettings,
+        chat_settings: ChatSettings,
+        replay_dir: Option<PathBuf>,
+        max_rooms: usize,
+    ) -> Self {
+        let mut rooms = HashMap::new();
+        rooms.insert(
+            DEFAULT_ROOM.to_string(),
+            Room::new(DEFAULT_ROOM, main_game, chat_settings.clone()),
+        );
+        Rooms {
+            rooms,
+            default_settings,
+            chat_settings,
+            replay_dir,
+            max_rooms,
+            lobby: HashMap::new(),
+            last_listing: Vec::new(),
+        }
+    }
+
+    // Room names aren't case sensitive
+    pub fn get(&self, name: &str) -> Option<Room> {
+        self.rooms.get(&name.to_lowercase()).cloned()
+    }
+
+    pub fn main(&self) -> Room {
+        self.rooms[DEFAULT_ROOM].clone()
+    }
+
+    // Hand over any rooms the closed connection was hosting
+    pub fn connection_closed(&mut self, token: Token) {
+        for room in self.rooms.values() {
+            room.replace_host(token);
+        }
+        self.lobby.remove(&token);
+    }
+
+    // The public rooms, in name order
+    pub fn list(&self) -> Vec<RoomSummary> {
+        let mut rooms: Vec<RoomSummary> = self
+            .rooms
+            .values()
+            .filter(|r| !r.private)
+            .map(|r| r.summary())
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        rooms
+    }
+
+    pub fn subscribe(&mut self, sender: ClientSender) {
+        self.lobby.insert(sender.token(), sender);
+    }
+
+    pub fn unsubscribe(&mut self, token: Token) {
+        self.lobby.remove(&token);
+    }
+
+    // Send the room list to the lobby if it has changed since it was last sent
+    pub fn notify_lobby(&mut self) {
+        let rooms = self.list();
+        if rooms == self.last_listing {
+            return;
+        }
+        let message = SendableMessage::Rooms {
+            rooms: rooms.clone(),
+        };
+        for sender in self.lobby.values() {
+            if let Err(e) = sender.send(&message) {
+                error!("Failed to send the room list: {}", e);
+            }
+        }
+        self.last_listing = rooms;
+    }
+
+    // Take out the rooms nobody has used for `idle`. The default room is never taken out.
+    pub fn expire_idle(&mut self, idle: Duration, now: Instant) -> Vec<Room> {
+        let expired: Vec<String> = self
+            .rooms
+            .values()
+            .filter(|r| r.name != DEFAULT_ROOM && r.is_idle(idle, now))
+            .map(|r| r.name.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|name| self.rooms.remove(name))
+            .collect()
+    }
+
+    pub fn create(
+        &mut self,
+        name: &str,
+        password: Option<String>,
+        invite_only: bool,
+        private: bool,
+        settings: Option<GameSettings>,
+        host: Token,
+    ) -> Result<Room, RoomError> {
+        let name = name.to_lowercase();
+        if !is_valid_room_name(&name) {
+            return Err(RoomError::InvalidName);
+        }
+        if self.rooms.contains_key(&name) {
+            return Err(RoomError::AlreadyExists);
+        }
+        if self.max_rooms != 0 && self.rooms.len() >= self.max_rooms {
+            return Err(RoomError::TooManyRooms);
+        }
+
+        let settings = settings.unwrap_or_else(|| self.default_settings.clone());
+        let mut game = RedOrBlack::with_settings(Vec::new(), settings);
+        if let Some(ref dir) = self.replay_dir {
+            game.record_events(room_replay_dir(dir, &name));
+        }
+        let mut room = Room::new(&name, game, self.chat_settings.clone());
+        room.private = private;
+        {
+            let mut access = room.access.borrow_mut();
+            access.password = password.filter(|p| !p.is_empty());
+            if invite_only {
+                access.invite_code = Some(new_invite_code());
+            }
+            access.host = Some(host);
+        }
+        info!("Created room {}", name);
+        self.rooms.insert(name, room.clone());
+        Ok(room)
+    }
+}
+
+#[cfg(test)]
+mod rooms {
+    use super::*;
+
+    fn rooms() -> Rooms {
+        Rooms::new(
+            RedOrBlack::with_settings(Vec::new(), GameSettings::default()),
+            GameSettings::default(),
+            ChatSettings::default(),
+            None,
+            0,
+        )
+    }
+
+    #[test]
+    fn main_room_always_exists() {
+        let rooms = rooms();
+        assert_eq!(rooms.main().name, DEFAULT_ROOM);
+        assert!(rooms.get("MAIN").is_some());
+        assert!(rooms.get("other").is_none());
+    }
+
+    #[test]
+    fn create_rooms() {
+        let mut rooms = rooms();
+        let room = rooms
+            .create("Friday-Night", None, false, false, None, Token(1))
+            .unwrap();
+        assert_eq!(room.name, "friday-night");
+        assert!(rooms.get("friday-night").is_some());
+        assert_eq!(
+            rooms
+                .create("friday-night", None, false, false, None, Token(2))
+                .err(),
+            Some(RoomError::AlreadyExists)
+        );
+        assert_eq!(
+            rooms
+                .create("no spaces", None, false, false, None, Token(2))
+                .err(),
+            Some(RoomError::InvalidName)
+        );
+    }
+
+    #[test]
+    fn room_limit() {
+        let mut rooms = rooms();
+        rooms.max_rooms = 2;
+        rooms
+            .create("first", None, false, false, None, Token(1))
+            .unwrap();
+        assert_eq!(
+            rooms
+                .create("second", None, false, false, None, Token(1))
+                .err(),
+            Some(RoomError::TooManyRooms)
+        );
+
+        // Rooms that close make space for new ones
+        rooms.expire_idle(Duration::from_secs(0), Instant::now());
+        assert!(rooms
+            .create("second", None, false, false, None, Token(1))
+            .is_ok());
+    }
+
+    #[test]
+    fn private_rooms_are_hidden() {
+        let mut rooms = rooms();
+        rooms
+            .create("secret", None, false, true, None, Token(1))
+            .unwrap();
+        rooms
+            .create(
+                "locked",
+                Some("pw".to_string()),
+                false,
+                false,
+                None,
+                Token(1),
+            )
+            .unwrap();
+        let listed = rooms.list();
+        let names: Vec<&str> = listed.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["locked", "main"]);
+        assert!(listed[0].password_required);
+        assert!(!listed[1].password_required);
+        assert_eq!(listed[1].state, RoomState::Lobby);
+        assert_eq!(listed[1].players, 0);
+    }
+
+    #[test]
+    fn passwords() {
+        let mut rooms = rooms();
+        let room = rooms
+            .create(
+                "locked",
+                Some("hunter2".to_string()),
+                false,
+                false,
+                None,
+                Token(1),
+            )
+            .unwrap();
+        let access = room.access.borrow();
+        assert_eq!(access.check(None), Err(RoomError::CodeRequired));
+        assert_eq!(access.check(Some("hunter3")), Err(RoomError::WrongCode));
+        assert_eq!(access.check(Some("hunter2")), Ok(()));
+        // Rooms without a password or invite code are open
+        assert_eq!(rooms.main().access.borrow().check(None), Ok(()));
+    }
+
+    #[test]
+    fn invite_codes() {
+        let mut rooms = rooms();
+        let room = rooms
+            .create("invite", None, true, false, None, Token(1))
+            .unwrap();
+        let mut access = room.access.borrow_mut();
+        let code = access.invite_code.clone().unwrap();
+        assert_eq!(code.len(), INVITE_CODE_LENGTH);
+        assert!(code.bytes().all(|c| INVITE_CODE_CHARS.contains(&c)));
+        assert_eq!(access.check(Some(&code.to_lowercase())), Ok(()));
+
+        // Only the host can change the code, and the old one stops working
+        assert_eq!(access.rotate_invite_code(Token(2)), Err(RoomError::NotHost));
+        let mut new_code = access.rotate_invite_code(Token(1)).unwrap();
+        while new_code == code {
+            new_code = access.rotate_invite_code(Token(1)).unwrap();
+        }
+        assert_eq!(access.check(Some(&code)), Err(RoomError::WrongCode));
+        assert_eq!(access.check(Some(&new_code)), Ok(()));
+    }
+
+    #[test]
+    fn idle_rooms_expire() {
+        let mut rooms = rooms();
+        let quiet = rooms
+            .create("quiet", None, false, false, None, Token(1))
+            .unwrap();
+        let busy = rooms
+            .create("busy", None, false, false, None, Token(2))
+            .unwrap();
+        let now = Instant::now();
+        quiet.last_active.set(now - Duration::from_secs(120));
+        busy.last_active.set(now);
+        rooms.main().last_active.set(now - Duration::from_secs(120));
+
+        let expired = rooms.expire_idle(Duration::from_secs(60), now);
+        let names: Vec<&str> = expired.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["quiet"]);
+        assert!(rooms.get("quiet").is_none());
+        assert!(rooms.get("busy").is_some());
+        assert!(rooms.get(DEFAULT_ROOM).is_some());
+    }
+
+    #[test]
+    fn bots_are_listed_and_play_their_turns() {
+        let rooms = rooms();
+        let room = rooms.main();
+        {
+            let mut game = room.game.borrow_mut();
+            game.add_player("Red Bot".to_string());
+            game.add_player("amy".to_string());
+        }
+        room.bots
+            .borrow_mut()
+            .insert("Red Bot".to_string(), BotStrategy::AlwaysRed);
+        match room.players_message() {
+            SendableMessage::Players { players, details } => {
+                assert_eq!(players, vec!["Red Bot"]);
+                assert!(details[0].bot);
+            }
+            other => panic!("Expected players, got {:?}", other),
+        }
+        assert_eq!(
+            room.bot_to_play(),
+            Some(("Red Bot".to_string(), BotStrategy::AlwaysRed))
+        );
+        room.game.borrow_mut().next_player();
+        assert_eq!(room.bot_to_play(), None);
+    }
+}
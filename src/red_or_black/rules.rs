@@ -1,35 +1,394 @@
+use super::events::{self, EventLog, GameEvent, LoggedEvent};
 use super::history::*;
 use super::messages::CardColour;
-use deck::{Card, Deck, Suit};
-use std::collections::VecDeque;
+use super::settings::{
+    CardCounting, GameLength, GameSettings, PlayMode, ReshufflePolicy, TeamPenalty,
+};
+use super::stats::{self, PlayerStats, TeamStats};
+use deck::{Card, Deck, DeckStats, Suit};
+use rand;
+use schemars::JsonSchema;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum DeckExhaustedReason {
+    DeckFinished,
+    PenetrationReached,
+    DiscardsRecycled,
+    GameEnded,
+}
+
+// Sent to the clients whenever the deck is replaced (or the game ends because it ran out)
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct DeckExhausted {
+    pub reason: DeckExhaustedReason,
+    pub deck_size: usize,
+}
+
+// How long one player drinks for, when a wrong guess is shared out in a team
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PenaltyShare {
+    pub username: String,
+    pub seconds: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TeamError {
+    TeamsOff,
+    NoSuchTeam,
+    NoSuchPlayer,
+}
+
+impl TeamError {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            TeamError::TeamsOff => "This game isn't played in teams",
+            TeamError::NoSuchTeam => "There's no team with that number",
+            TeamError::NoSuchPlayer => "There's no player with that name",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TurnOutcome {
+    pub correct: bool,
+    pub penalty: u16,
+    pub next_player: Option<String>,
+    pub card: Card,
+    pub cards_left: usize,
+    pub deck_exhausted: Option<DeckExhausted>,
+    // The turn as it was added to the history
+    pub item: HistoryItem,
+    // Who drinks for a wrong guess when playing in teams
+    pub shares: Vec<PenaltyShare>,
+}
+
+// Why a guess wasn't collected in everyone plays mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GuessError {
+    NotPlaying,
+    AlreadyGuessed,
+    GameOver,
+}
+
+// The result of revealing a card that everyone guessed at once
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoundOutcome {
+    pub card: Card,
+    // A history item for each player that guessed, in seat order
+    pub items: Vec<HistoryItem>,
+    // What each player that got it wrong has to take
+    pub penalty: u16,
+    pub everyone_right: bool,
+    pub cards_left: usize,
+    pub deck_exhausted: Option<DeckExhausted>,
+    // Who drinks for the wrong guesses when playing in teams, added up for each player
+    pub shares: Vec<PenaltyShare>,
+}
+
+// Whether the penalty goes up after a card in everyone plays mode: every player has to have
+// guessed it and been right, so players that ran out of time count as wrong
+pub fn everyone_right(players: &[String], items: &[HistoryItem]) -> bool {
+    players.iter().all(|p| items.iter().any(|i| &i.username == p))
+        && items.iter().all(|i| i.outcome)
+}
+
+pub fn now_millis() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0));
+    now.as_secs() * 1000 + u64::from(now.subsec_millis())
+}
+
+// Ids start with the time so that they sort in the order the games were played
+fn new_game_id() -> String {
+    format!("{:x}-{:04x}", now_millis(), rand::random::<u16>())
+}
 
 pub struct RedOrBlack {
+    settings: GameSettings,
     usernames: Vec<String>,
     index: usize,
     penalty: u16,
     deck: Deck,
+    // The size of the deck when it was last shuffled, used to work out penetration
+    deck_size: usize,
+    // Every card dealt from the current deck
+    discards: Vec<Card>,
+    finished: bool,
     card_history: CardHistory,
     game_history: GameHistory,
+    // Every turn played this game, game_history only keeps the most recent ones
+    full_history: Vec<HistoryItem>,
+    // When the first turn was played
+    started_at: Option<Instant>,
     turn_number: u16,
+    game_id: String,
+    // Where the event log for each game is kept, if games are being recorded
+    events_dir: Option<PathBuf>,
+    event_log: Option<EventLog>,
+    // Players from a restored game that haven't reconnected yet, and when the game was restored
+    awaiting_reconnect: Vec<String>,
+    restored_at: Option<Instant>,
+    // The guesses collected for the current card in everyone plays mode, and when the window for
+    // the rest of them closes
+    round_guesses: Vec<(String, CardColour)>,
+    round_closes_at: Option<Instant>,
+    // The team each player is in when playing in teams, numbered from 1
+    teams: HashMap<String, u8>,
+    // How many turns each team has had, to rotate through its players, and whose turn it is
+    // (counting from 0)
+    team_turns: Vec<usize>,
+    team_index: usize,
 }
 
 impl RedOrBlack {
+    #[cfg(test)]
     pub fn new(usernames: Vec<String>) -> Self {
-        RedOrBlack {
+        Self::with_settings(usernames, GameSettings::default())
+    }
+
+    pub fn with_settings(usernames: Vec<String>, settings: GameSettings) -> Self {
+        let team_count = usize::from(settings.teams);
+        let mut game = RedOrBlack {
+            settings,
             usernames,
             index: 0,
             penalty: 5,
             deck: Deck::new_shuffled(),
+            deck_size: 52,
+            discards: Vec::new(),
+            finished: false,
             card_history: CardHistory::new(3),
             game_history: GameHistory::new(40),
+            full_history: Vec::new(),
+            started_at: None,
             turn_number: 1,
+            game_id: new_game_id(),
+            events_dir: None,
+            event_log: None,
+            awaiting_reconnect: Vec::new(),
+            restored_at: None,
+            round_guesses: Vec::new(),
+            round_closes_at: None,
+            teams: HashMap::new(),
+            team_turns: vec![0; team_count],
+            team_index: 0,
+        };
+        if game.teams_enabled() {
+            for username in game.usernames.clone() {
+                let team = game.smallest_team();
+                game.teams.insert(username, team);
+            }
+        }
+        game
+    }
+
+    // Rebuild the most recent game in the directory from its event log, e.g. after the server was
+    // restarted. The players in it get their seats back if they log in again. Returns None if
+    // there isn't an unfinished game to restore.
+    pub fn restore(settings: GameSettings, dir: PathBuf) -> io::Result<Option<Self>> {
+        let game_id = match events::list_games(&dir)?.pop() {
+            Some(game_id) => game_id,
+            None => return Ok(None),
+        };
+        let logged_events = events::read_events(&dir, &game_id)?;
+        // The game ran to the end or was thrown away, so there's nothing to pick up
+        if logged_events.is_empty() || events::game_finished(&logged_events) {
+            return Ok(None);
+        }
+
+        let mut game = Self::with_settings(Vec::new(), settings);
+        for logged in &logged_events {
+            game.apply(&logged.event);
+        }
+        info!(
+            "Restored game {} with {} turns played",
+            game_id,
+            game.turns_played()
+        );
+        game.event_log = Some(EventLog::resume(&dir, &game_id)?);
+        game.events_dir = Some(dir);
+        game.game_id = game_id;
+        game.awaiting_reconnect = game.usernames.clone();
+        game.restored_at = Some(Instant::now());
+        Ok(Some(game))
+    }
+
+    pub fn game_id(&self) -> &str {
+        &self.game_id
+    }
+
+    pub fn settings(&self) -> &GameSettings {
+        &self.settings
+    }
+
+    // Start writing an event log for every game to the directory, so they can be replayed
+    pub fn record_events(&mut self, dir: PathBuf) {
+        self.events_dir = Some(dir);
+        self.start_event_log();
+    }
+
+    fn start_event_log(&mut self) {
+        self.event_log = self
+            .events_dir
+            .as_ref()
+            .map(|dir| EventLog::new(dir, &self.game_id));
+        let cards = self.deck.cards().to_vec();
+        self.log_event(GameEvent::DeckShuffled {
+            cards,
+            reason: None,
+        });
+        for username in self.usernames.clone() {
+            let team = self.team_of(&username);
+            self.log_event(GameEvent::PlayerJoined {
+                username: username.clone(),
+            });
+            if let Some(team) = team {
+                self.log_event(GameEvent::TeamAssigned { username, team });
+            }
+        }
+    }
+
+    fn log_event(&mut self, event: GameEvent) {
+        if let Some(ref mut event_log) = self.event_log {
+            let logged = LoggedEvent {
+                timestamp: now_millis(),
+                event,
+            };
+            if let Err(e) = event_log.append(logged) {
+                error!("Failed to write event for game {}: {}", self.game_id, e);
+            }
+        }
+    }
+
+    // Every change to the game is made by recording an event, so that the game can be rebuilt
+    // from its event log
+    fn record(&mut self, event: GameEvent) {
+        self.apply(&event);
+        self.log_event(event);
+    }
+
+    fn apply(&mut self, event: &GameEvent) {
+        match *event {
+            GameEvent::DeckShuffled {
+                ref cards,
+                ref reason,
+            } => {
+                let recycled = reason
+                    .as_ref()
+                    .map(|r| r.reason == DeckExhaustedReason::DiscardsRecycled)
+                    .unwrap_or(false);
+                if recycled {
+                    // The cards still on the table go back in the next time the deck is recycled
+                    let on_table = self.cards_on_table();
+                    self.discards.retain(|c| on_table.contains(c));
+                } else {
+                    self.discards.clear();
+                }
+                self.deck = Deck::from_cards(cards.clone());
+                self.deck_size = cards.len();
+            }
+            GameEvent::PlayerJoined { ref username } => {
+                self.usernames.push(username.clone());
+            }
+            GameEvent::PlayerLeft { ref username } => {
+                // If the current player is the player being removed, then we need to progress
+                // the game to the next player. In teams the turn stays with the team, and goes to
+                // the next teammate once the player is gone.
+                if !self.teams_enabled()
+                    && self.get_current_player().map(|p| p == username) == Some(true)
+                {
+                    self.next_player();
+                }
+
+                // Find posistion of player to remove
+                if let Some(index) = self.usernames.iter().position(|u| u == username) {
+                    self.usernames.remove(index);
+                }
+                self.teams.remove(username);
+            }
+            GameEvent::TeamAssigned {
+                ref username,
+                team,
+            } => {
+                self.teams.insert(username.clone(), team);
+            }
+            GameEvent::TurnPlayed { ref item, .. } => {
+                if self.started_at.is_none() {
+                    self.started_at = Some(Instant::now());
+                }
+                self.deck.pop();
+                self.discards.push(item.card);
+                self.card_history.push(item.card);
+                if item.outcome {
+                    self.increment_penalty();
+                } else {
+                    self.reset_penalty();
+                }
+                self.full_history.push(item.clone());
+                self.game_history.push(item.clone());
+                self.turn_number = item.turn_number + 1;
+                self.next_player();
+            }
+            GameEvent::RoundPlayed { ref items, .. } => {
+                let card = match items.first() {
+                    Some(item) => item.card,
+                    None => return,
+                };
+                if self.started_at.is_none() {
+                    self.started_at = Some(Instant::now());
+                }
+                self.deck.pop();
+                self.discards.push(card);
+                self.card_history.push(card);
+                if everyone_right(&self.usernames, items) {
+                    self.increment_penalty();
+                } else {
+                    self.reset_penalty();
+                }
+                self.full_history.extend(items.iter().cloned());
+                for item in items {
+                    self.game_history.push(item.clone());
+                }
+                self.turn_number = items[0].turn_number + 1;
+                self.round_guesses.clear();
+                self.round_closes_at = None;
+            }
+            GameEvent::GameEnded => {
+                self.finished = true;
+            }
+            GameEvent::GameReset => {}
         }
     }
 
+    pub fn get_full_history(&self) -> &Vec<HistoryItem> {
+        &self.full_history
+    }
+
+    pub fn player_stats(&self) -> Vec<PlayerStats> {
+        stats::player_stats(&self.full_history)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
     pub fn get_card_history(&self) -> &VecDeque<Option<Card>> {
         self.card_history.get_history()
     }
 
+    fn cards_on_table(&self) -> Vec<Card> {
+        self.card_history
+            .get_history()
+            .iter()
+            .filter_map(|c| *c)
+            .collect()
+    }
+
     pub fn get_game_history(&self) -> &Vec<HistoryItem> {
         self.game_history.get_history()
     }
@@ -38,6 +397,33 @@ impl RedOrBlack {
         self.deck.len()
     }
 
+    pub fn reds_left(&self) -> usize {
+        self.deck.reds_left()
+    }
+
+    // The number of the turn being played now
+    pub fn turn_number(&self) -> u16 {
+        self.turn_number
+    }
+
+    // In everyone plays mode a turn is one card, however many players guessed it
+    pub fn turns_played(&self) -> usize {
+        usize::from(self.turn_number - 1)
+    }
+
+    pub fn usernames(&self) -> &[String] {
+        &self.usernames
+    }
+
+    // Returns None if card counting is turned off for this game
+    pub fn deck_stats(&self) -> Option<DeckStats> {
+        match self.settings.card_counting {
+            CardCounting::Off => None,
+            CardCounting::Colours => Some(self.deck.stats(false)),
+            CardCounting::Values => Some(self.deck.stats(true)),
+        }
+    }
+
     pub fn get_penalty(&self) -> u16 {
         self.penalty
     }
@@ -53,6 +439,12 @@ impl RedOrBlack {
     }
 
     pub fn get_current_player(&mut self) -> Option<&String> {
+        if self.teams_enabled() {
+            let player = self
+                .team_with_players(self.team_index)
+                .and_then(|team| self.team_player(team, 0))?;
+            return self.usernames.iter().find(|u| **u == player);
+        }
         // Check bounds incase len has shrunk from players leaving
         if self.index >= self.usernames.len() {
             self.index = 0;
@@ -61,6 +453,14 @@ impl RedOrBlack {
     }
 
     pub fn next_player(&mut self) -> Option<&String> {
+        // Turns go to each team in turn, and rotate through the players within a team
+        if self.teams_enabled() {
+            if let Some(team) = self.team_with_players(self.team_index) {
+                self.team_turns[team] += 1;
+                self.team_index = (team + 1) % self.team_turns.len();
+            }
+            return self.get_current_player();
+        }
         // Check bounds incase len has shrunk from players leaving
         self.index += 1;
         if self.index >= self.usernames.len() {
@@ -70,25 +470,137 @@ impl RedOrBlack {
         self.usernames.get(self.index)
     }
 
-    pub fn remove_player(&mut self, username: &str) -> bool {
-        let mut changed_turn = false;
-        // First check if there is a current player
-        if let Some(current_player) = self.get_current_player().as_ref() {
-            // If the current player is the player being removed, then we need to progress the game
-            // to the next player
-            if current_player == &username {
-                changed_turn = true;
-            }
+    // Who next_player would move the turn on to, without moving it
+    fn peek_next_player(&self) -> Option<String> {
+        if self.teams_enabled() {
+            let team = self.team_with_players(self.team_index)?;
+            let next = self.team_with_players((team + 1) % self.team_turns.len())?;
+            return self.team_player(next, if next == team { 1 } else { 0 });
+        }
+        if self.usernames.is_empty() {
+            return None;
         }
+        let current = if self.index >= self.usernames.len() {
+            0
+        } else {
+            self.index
+        };
+        let next = (current + 1) % self.usernames.len();
+        self.usernames.get(next).cloned()
+    }
 
-        // If the turn needs to changed, then change it
-        if changed_turn {
-            self.next_player();
+    pub fn teams_enabled(&self) -> bool {
+        self.settings.teams > 0
+    }
+
+    pub fn team_of(&self, username: &str) -> Option<u8> {
+        self.teams.get(username).cloned()
+    }
+
+    // The players in a team, in the order they joined the game
+    pub fn team_members(&self, team: u8) -> Vec<String> {
+        self.usernames
+            .iter()
+            .filter(|u| self.team_of(u) == Some(team))
+            .cloned()
+            .collect()
+    }
+
+    // New players go in the team with the fewest players
+    fn smallest_team(&self) -> u8 {
+        (1..=self.settings.teams)
+            .min_by_key(|&team| self.team_members(team).len())
+            .unwrap_or(1)
+    }
+
+    // The first team with anyone in it, starting from `from` (counting from 0)
+    fn team_with_players(&self, from: usize) -> Option<usize> {
+        let count = self.team_turns.len();
+        (0..count)
+            .map(|i| (from + i) % count)
+            .find(|&team| !self.team_members(team as u8 + 1).is_empty())
+    }
+
+    // Who plays when it's the team's go, `ahead` of the team's turns from now
+    fn team_player(&self, team: usize, ahead: usize) -> Option<String> {
+        let members = self.team_members(team as u8 + 1);
+        if members.is_empty() {
+            return None;
         }
+        Some(members[(self.team_turns[team] + ahead) % members.len()].clone())
+    }
 
-        // Find posistion of player to remove
-        if let Some(index) = self.usernames.iter().position(|u| u == username) {
-            self.usernames.remove(index);
+    // Move a player to another team, e.g. when the host is picking the teams
+    pub fn assign_team(&mut self, username: &str, team: u8) -> Result<(), TeamError> {
+        if !self.teams_enabled() {
+            return Err(TeamError::TeamsOff);
+        }
+        if team == 0 || team > self.settings.teams {
+            return Err(TeamError::NoSuchTeam);
+        }
+        if !self.usernames.iter().any(|u| u == username) {
+            return Err(TeamError::NoSuchPlayer);
+        }
+        self.record(GameEvent::TeamAssigned {
+            username: username.to_string(),
+            team,
+        });
+        Ok(())
+    }
+
+    // The score for each team, or nothing if the game isn't played in teams
+    pub fn team_stats(&self) -> Vec<TeamStats> {
+        let teams: Vec<Vec<String>> = (1..=self.settings.teams)
+            .map(|team| self.team_members(team))
+            .collect();
+        stats::team_stats(&self.full_history, &teams)
+    }
+
+    // Who drinks for a wrong guess by someone in a team, following the house rule
+    fn penalty_shares(&self, username: &str, penalty: u16) -> Vec<PenaltyShare> {
+        let team = match self.team_of(username) {
+            Some(team) => team,
+            None => return Vec::new(),
+        };
+        let players = match self.settings.team_penalty {
+            TeamPenalty::Split => self.team_members(team),
+            TeamPenalty::Guesser => vec![username.to_string()],
+        };
+        let seconds = penalty.div_ceil(players.len() as u16);
+        players
+            .into_iter()
+            .map(|username| PenaltyShare { username, seconds })
+            .collect()
+    }
+
+    // The shares for every wrong guess in a round, players that have to drink for more than one
+    // of them get one share with the seconds added together
+    fn round_shares(&self, items: &[HistoryItem], penalty: u16) -> Vec<PenaltyShare> {
+        let mut shares: Vec<PenaltyShare> = Vec::new();
+        for item in items.iter().filter(|i| !i.outcome) {
+            for share in self.penalty_shares(&item.username, penalty) {
+                match shares.iter_mut().find(|s| s.username == share.username) {
+                    Some(existing) => existing.seconds += share.seconds,
+                    None => shares.push(share),
+                }
+            }
+        }
+        shares
+    }
+
+    pub fn remove_player(&mut self, username: &str) -> bool {
+        // First check if the player being removed is the current player
+        let changed_turn = self.get_current_player().map(|p| p == username) == Some(true);
+
+        self.awaiting_reconnect.retain(|u| u != username);
+        self.round_guesses.retain(|(u, _)| u != username);
+        if self.round_guesses.is_empty() {
+            self.round_closes_at = None;
+        }
+        if self.usernames.iter().any(|u| u == username) {
+            self.record(GameEvent::PlayerLeft {
+                username: username.to_string(),
+            });
         }
 
         if self.usernames.is_empty() {
@@ -101,17 +613,103 @@ impl RedOrBlack {
     }
 
     pub fn add_player(&mut self, p: String) {
-        self.usernames.push(p);
+        // Players from a restored game take back their old seat
+        if let Some(index) = self.awaiting_reconnect.iter().position(|u| u == &p) {
+            info!("{} has reconnected", p);
+            self.awaiting_reconnect.remove(index);
+            return;
+        }
+        self.record(GameEvent::PlayerJoined {
+            username: p.clone(),
+        });
+        if self.teams_enabled() {
+            let team = self.smallest_team();
+            self.record(GameEvent::TeamAssigned { username: p, team });
+        }
     }
 
-    pub fn draw_card(&mut self) -> Card {
-        if let Some(card) = self.deck.pop() {
-            card
+    // Remove players from a restored game that haven't reconnected within the grace period.
+    // Returns true if anyone was removed.
+    pub fn expire_unclaimed_players(&mut self, grace: Duration) -> bool {
+        let expired = match self.restored_at {
+            Some(restored_at) => restored_at.elapsed() >= grace,
+            None => false,
+        };
+        if !expired || self.awaiting_reconnect.is_empty() {
+            return false;
+        }
+
+        for username in self.awaiting_reconnect.clone() {
+            info!("{} didn't reconnect in time, removing them", username);
+            self.remove_player(&username);
+        }
+        self.restored_at = None;
+        true
+    }
+
+    fn end_game(&mut self) {
+        if !self.finished {
+            self.record(GameEvent::GameEnded);
+        }
+    }
+
+    // Apply the reshuffle policy after a card has been drawn
+    fn check_deck(&mut self) -> Option<DeckExhausted> {
+        let dealt = self.deck_size - self.deck.len();
+        let policy = if self.settings.game_length == GameLength::OneDeck {
+            ReshufflePolicy::EndGame
         } else {
-            info!("Deck finished!!! re-shuffling");
-            self.deck = Deck::new_shuffled();
-            self.deck.pop().unwrap()
+            self.settings.reshuffle
+        };
+        let reason = match policy {
+            ReshufflePolicy::Immediate if self.deck.is_empty() => DeckExhaustedReason::DeckFinished,
+            ReshufflePolicy::Penetration(percent)
+                if dealt * 100 >= self.deck_size * percent as usize =>
+            {
+                DeckExhaustedReason::PenetrationReached
+            }
+            ReshufflePolicy::RecycleDiscards if self.deck.is_empty() => {
+                DeckExhaustedReason::DiscardsRecycled
+            }
+            ReshufflePolicy::EndGame if self.deck.is_empty() => DeckExhaustedReason::GameEnded,
+            _ => return None,
+        };
+
+        let new_deck = match reason {
+            DeckExhaustedReason::GameEnded => {
+                info!("Deck finished, game over");
+                self.end_game();
+                None
+            }
+            DeckExhaustedReason::DiscardsRecycled => {
+                // The cards still showing in the card history stay on the table
+                let on_table = self.cards_on_table();
+                let recycled: Vec<Card> = self
+                    .discards
+                    .iter()
+                    .filter(|c| !on_table.contains(c))
+                    .cloned()
+                    .collect();
+                info!("Deck finished, recycling {} discards", recycled.len());
+                Some(Deck::shuffled_from(recycled))
+            }
+            _ => {
+                info!("Re-shuffling deck: {:?}", reason);
+                Some(Deck::new_shuffled())
+            }
+        };
+
+        let deck_exhausted = DeckExhausted {
+            reason,
+            deck_size: new_deck.as_ref().map(|d| d.len()).unwrap_or(0),
+        };
+        if let Some(deck) = new_deck {
+            self.record(GameEvent::DeckShuffled {
+                cards: deck.cards().to_vec(),
+                reason: Some(deck_exhausted.clone()),
+            });
         }
+        Some(deck_exhausted)
     }
 
     pub fn validate_guess(&self, guess: &CardColour, card: Card) -> bool {
@@ -119,47 +717,263 @@ impl RedOrBlack {
             || guess == &CardColour::Red && (card.suit == Suit::Heart || card.suit == Suit::Diamond)
     }
 
+    // When a game with a time limit runs out of time, once the first turn has been played
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.settings.game_length {
+            GameLength::Minutes(minutes) => self
+                .started_at
+                .map(|t| t + Duration::from_secs(minutes * 60)),
+            _ => None,
+        }
+    }
+
+    // End a game with a time limit once the time is up, even if nobody has played since.
+    // Returns true if the game ended.
+    pub fn check_deadline(&mut self, now: Instant) -> bool {
+        match self.deadline() {
+            Some(deadline) if !self.finished && now >= deadline => {
+                info!("Out of time, game over");
+                self.end_game();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn check_game_length(&mut self) {
+        let over = match self.settings.game_length {
+            GameLength::Turns(turns) => self.turn_number > turns,
+            GameLength::Minutes(_) => self.deadline().is_some_and(|d| Instant::now() >= d),
+            GameLength::Unlimited | GameLength::OneDeck => false,
+        };
+        if over {
+            info!("Game length reached, game over");
+            self.end_game();
+        }
+    }
+
+    // Make sure there's a card to draw, whatever the reshuffle policy left behind
+    fn refill_deck(&mut self) {
+        if self.deck.is_empty() {
+            info!("Deck finished!!! re-shuffling");
+            self.record(GameEvent::DeckShuffled {
+                cards: Deck::new_shuffled().cards().to_vec(),
+                reason: None,
+            });
+        }
+    }
+
     // validate guess, and change players turn
-    // return (correct, penalty, next user, the card drawn, and number of cards left)
-    pub fn play_turn(&mut self, guess: &CardColour) -> (bool, u16, Option<&String>, Card, usize) {
-        let card = self.draw_card();
-        self.card_history.push(card);
+    pub fn play_turn(&mut self, guess: &CardColour) -> TurnOutcome {
+        self.refill_deck();
+        let card = self.deck.peek().unwrap();
         let correct = self.validate_guess(guess, card);
+        // When the guess is right the penalty goes up, otherwise the current penalty is served
         let penalty = if correct {
-            self.increment_penalty()
+            self.penalty + 5
         } else {
-            let penalty = self.penalty;
-            self.reset_penalty();
-            penalty
+            self.penalty
         };
 
+        let username = self
+            .get_current_player()
+            .cloned()
+            .unwrap_or_else(|| "".to_string());
+        let shares = if correct {
+            Vec::new()
+        } else {
+            self.penalty_shares(&username, penalty)
+        };
         let history_item = HistoryItem {
-            username: self
-                .get_current_player()
-                .cloned()
-                .unwrap_or_else(|| "".to_string()),
+            team: self.team_of(&username),
+            username,
             guess: guess.clone(),
             outcome: correct,
             card,
             penalty,
             turn_number: self.turn_number,
+            timestamp: now_millis(),
         };
 
-        self.game_history.push(history_item);
-        self.turn_number += 1;
+        let next_player = self.peek_next_player();
+        let cards_left = self.deck.len() - 1;
+        self.record(GameEvent::TurnPlayed {
+            item: history_item.clone(),
+            next_player: next_player.clone(),
+            cards_left,
+        });
+
+        let deck_exhausted = self.check_deck();
+        self.check_game_length();
+        TurnOutcome {
+            correct,
+            penalty,
+            next_player,
+            card,
+            cards_left: self.deck.len(),
+            deck_exhausted,
+            item: history_item,
+            shares,
+        }
+    }
+
+    pub fn everyone_plays(&self) -> bool {
+        match self.settings.play_mode {
+            PlayMode::EveryonePlays(_) => true,
+            PlayMode::Rotation => false,
+        }
+    }
+
+    // How long the other players have to guess once the first guess for a card is in
+    pub fn guess_window(&self) -> Option<Duration> {
+        match self.settings.play_mode {
+            PlayMode::EveryonePlays(secs) => Some(Duration::from_secs(secs)),
+            PlayMode::Rotation => None,
+        }
+    }
+
+    pub fn has_guessed(&self, username: &str) -> bool {
+        self.round_guesses.iter().any(|(u, _)| u == username)
+    }
+
+    // The players that still haven't guessed the current card
+    pub fn waiting_for(&self) -> Vec<String> {
+        self.usernames
+            .iter()
+            .filter(|u| !self.has_guessed(u))
+            .cloned()
+            .collect()
+    }
+
+    // None until the first guess for the current card is in
+    pub fn round_closes_at(&self) -> Option<Instant> {
+        self.round_closes_at
+    }
+
+    // Everyone has guessed, so there's no need to wait for the window to close
+    pub fn round_complete(&self) -> bool {
+        !self.round_guesses.is_empty() && self.usernames.iter().all(|u| self.has_guessed(u))
+    }
+
+    // Collect a guess for the current card in everyone plays mode. The first guess starts the
+    // window for everyone else. Returns true once every player has guessed.
+    pub fn submit_guess(
+        &mut self,
+        username: &str,
+        guess: CardColour,
+        now: Instant,
+    ) -> Result<bool, GuessError> {
+        self.add_guess(username, guess, Some(now))
+    }
+
+    // Bots guess as soon as the card is up, so their guesses don't start the window
+    pub fn submit_bot_guess(
+        &mut self,
+        username: &str,
+        guess: CardColour,
+    ) -> Result<bool, GuessError> {
+        self.add_guess(username, guess, None)
+    }
+
+    fn add_guess(
+        &mut self,
+        username: &str,
+        guess: CardColour,
+        opened_at: Option<Instant>,
+    ) -> Result<bool, GuessError> {
+        if self.finished {
+            return Err(GuessError::GameOver);
+        }
+        if !self.usernames.iter().any(|u| u == username) {
+            return Err(GuessError::NotPlaying);
+        }
+        if self.has_guessed(username) {
+            return Err(GuessError::AlreadyGuessed);
+        }
+        if let (None, Some(now)) = (self.round_closes_at, opened_at) {
+            self.round_closes_at = self.guess_window().map(|window| now + window);
+        }
+        self.round_guesses.push((username.to_string(), guess));
+        Ok(self.round_complete())
+    }
 
-        let cards_left = self.deck.len();
-        let player = self.next_player();
-        (correct, penalty, player, card, cards_left)
+    // Turn over the card everyone has been guessing. Each player that got it wrong takes the
+    // penalty, and it only goes up if every player guessed and was right. Players that didn't
+    // guess in time sit the card out. Returns None if nobody has guessed yet.
+    pub fn reveal(&mut self) -> Option<RoundOutcome> {
+        if self.finished || self.round_guesses.is_empty() {
+            return None;
+        }
+        self.refill_deck();
+        let card = self.deck.peek().unwrap();
+        let mut guesses = self.round_guesses.clone();
+        guesses.sort_by_key(|(u, _)| self.usernames.iter().position(|p| p == u));
+
+        let penalty = self.penalty;
+        let timestamp = now_millis();
+        let mut items: Vec<HistoryItem> = guesses
+            .into_iter()
+            .map(|(username, guess)| HistoryItem {
+                outcome: self.validate_guess(&guess, card),
+                team: self.team_of(&username),
+                username,
+                guess,
+                card,
+                penalty,
+                turn_number: self.turn_number,
+                timestamp,
+            })
+            .collect();
+        let everyone_right = everyone_right(&self.usernames, &items);
+        if everyone_right {
+            for item in &mut items {
+                item.penalty += 5;
+            }
+        }
+        let shares = self.round_shares(&items, penalty);
+
+        let cards_left = self.deck.len() - 1;
+        self.record(GameEvent::RoundPlayed {
+            items: items.clone(),
+            cards_left,
+        });
+
+        let deck_exhausted = self.check_deck();
+        self.check_game_length();
+        Some(RoundOutcome {
+            card,
+            items,
+            penalty,
+            everyone_right,
+            cards_left: self.deck.len(),
+            deck_exhausted,
+            shares,
+        })
     }
 
     fn reset(&mut self) {
         info!("Reseting game");
+        // Mark the old game as over so it isn't restored
+        self.log_event(GameEvent::GameReset);
         self.penalty = 5;
         self.card_history = CardHistory::new(3);
         self.game_history = GameHistory::new(40);
+        self.full_history.clear();
+        self.started_at = None;
         self.deck = Deck::new_shuffled();
+        self.deck_size = self.deck.len();
+        self.discards.clear();
+        self.finished = false;
         self.turn_number = 1;
+        self.awaiting_reconnect.clear();
+        self.restored_at = None;
+        self.round_guesses.clear();
+        self.round_closes_at = None;
+        self.teams.clear();
+        self.team_turns = vec![0; self.team_turns.len()];
+        self.team_index = 0;
+        self.game_id = new_game_id();
+        self.start_event_log();
     }
 }
 
@@ -193,7 +1007,7 @@ mod unit {
             let mut correct_count = 1;
             let guess = CardColour::Red;
             // while we guess correctly the penalty should not change
-            while game.play_turn(&guess).0 == true {
+            while game.play_turn(&guess).correct {
                 correct_count += 1;
                 assert_eq!(game.get_penalty(), 5 * correct_count);
             }
@@ -212,7 +1026,7 @@ mod unit {
             let guess = CardColour::Black;
             assert_eq!(game.get_current_player(), None);
             assert_eq!(game.next_player(), None);
-            assert_eq!(game.play_turn(&guess).2, None);
+            assert_eq!(game.play_turn(&guess).next_player, None);
         }
 
         #[test]
@@ -222,8 +1036,8 @@ mod unit {
             assert_eq!(game.get_current_player(), Some(&"mick".to_string()));
             assert_eq!(game.next_player(), Some(&"mick".to_string()));
             assert_eq!(game.next_player(), Some(&"mick".to_string()));
-            assert_eq!(game.play_turn(&guess).2, Some(&"mick".to_string()));
-            assert_eq!(game.play_turn(&guess).2, Some(&"mick".to_string()));
+            assert_eq!(game.play_turn(&guess).next_player, Some("mick".to_string()));
+            assert_eq!(game.play_turn(&guess).next_player, Some("mick".to_string()));
         }
 
         #[test]
@@ -295,70 +1109,831 @@ mod unit {
         fn history_doesnt_grow() {
             let mut game = RedOrBlack::new(vec!["renton".to_string()]);
             let guess = CardColour::Red;
-            let (_, _, _, _, cards1) = game.play_turn(&guess);
-            let (_, _, _, card2, cards2) = game.play_turn(&guess);
-            let (_, _, _, card3, cards3) = game.play_turn(&guess);
-            let (_, _, _, card4, cards4) = game.play_turn(&guess);
+            let turn1 = game.play_turn(&guess);
+            let turn2 = game.play_turn(&guess);
+            let turn3 = game.play_turn(&guess);
+            let turn4 = game.play_turn(&guess);
             let history = game.get_card_history();
-            assert_eq!(cards1, 51);
-            assert_eq!(cards2, 50);
-            assert_eq!(cards3, 49);
-            assert_eq!(cards4, 48);
-            assert_eq!(history[0], Some(card4));
-            assert_eq!(history[1], Some(card3));
-            assert_eq!(history[2], Some(card2));
+            assert_eq!(turn1.cards_left, 51);
+            assert_eq!(turn2.cards_left, 50);
+            assert_eq!(turn3.cards_left, 49);
+            assert_eq!(turn4.cards_left, 48);
+            assert_eq!(history[0], Some(turn4.card));
+            assert_eq!(history[1], Some(turn3.card));
+            assert_eq!(history[2], Some(turn2.card));
             assert_eq!(history.len(), 3);
         }
     }
 
+    mod deck_stats {
+        use super::*;
+        use red_or_black::settings::{CardCounting, GameSettings};
+
+        fn game_with_counting(card_counting: CardCounting) -> RedOrBlack {
+            let settings = GameSettings {
+                card_counting,
+                ..GameSettings::default()
+            };
+            RedOrBlack::with_settings(vec!["spud".to_string()], settings)
+        }
+
+        #[test]
+        fn off_by_default() {
+            let game = RedOrBlack::new(vec!["spud".to_string()]);
+            assert_eq!(game.deck_stats(), None);
+        }
+
+        #[test]
+        fn full_deck_is_even() {
+            let stats = game_with_counting(CardCounting::Colours)
+                .deck_stats()
+                .unwrap();
+            assert_eq!(stats.cards_left, 52);
+            assert_eq!(stats.reds_left, 26);
+            assert_eq!(stats.blacks_left, 26);
+            assert_eq!(stats.red_probability, 0.5);
+            assert_eq!(stats.values_left, None);
+        }
+
+        #[test]
+        fn drawn_cards_are_counted() {
+            let mut game = game_with_counting(CardCounting::Values);
+            let card = game.play_turn(&CardColour::Red).card;
+            let stats = game.deck_stats().unwrap();
+            assert_eq!(stats.cards_left, 51);
+            if card.suit.is_red() {
+                assert_eq!(stats.reds_left, 25);
+                assert_eq!(stats.blacks_left, 26);
+            } else {
+                assert_eq!(stats.reds_left, 26);
+                assert_eq!(stats.blacks_left, 25);
+            }
+            assert_eq!(stats.red_probability, stats.reds_left as f64 / 51.0);
+
+            let values_left = stats.values_left.unwrap();
+            assert_eq!(values_left.len(), 13);
+            for value_count in values_left {
+                let expected = if value_count.value == card.value { 3 } else { 4 };
+                assert_eq!(value_count.count, expected);
+            }
+        }
+    }
+
+    mod reshuffle {
+        use super::*;
+        use red_or_black::settings::{GameSettings, ReshufflePolicy};
+
+        fn game_with_policy(reshuffle: ReshufflePolicy) -> RedOrBlack {
+            let settings = GameSettings {
+                reshuffle,
+                ..GameSettings::default()
+            };
+            RedOrBlack::with_settings(vec!["sick boy".to_string()], settings)
+        }
+
+        // Play turns until the deck is replaced, returning how many turns it took
+        fn play_until_exhausted(game: &mut RedOrBlack) -> (usize, TurnOutcome) {
+            for turn in 1..=52 {
+                let outcome = game.play_turn(&CardColour::Black);
+                if outcome.deck_exhausted.is_some() {
+                    return (turn, outcome);
+                }
+            }
+            panic!("Deck was never exhausted");
+        }
+
+        #[test]
+        fn immediate() {
+            let mut game = game_with_policy(ReshufflePolicy::Immediate);
+            let (turns, outcome) = play_until_exhausted(&mut game);
+            assert_eq!(turns, 52);
+            assert_eq!(outcome.cards_left, 52);
+            assert_eq!(
+                outcome.deck_exhausted,
+                Some(DeckExhausted {
+                    reason: DeckExhaustedReason::DeckFinished,
+                    deck_size: 52,
+                })
+            );
+            assert!(!game.is_finished());
+        }
+
+        #[test]
+        fn penetration() {
+            let mut game = game_with_policy(ReshufflePolicy::Penetration(75));
+            let (turns, outcome) = play_until_exhausted(&mut game);
+            assert_eq!(turns, 39);
+            assert_eq!(
+                outcome.deck_exhausted.unwrap().reason,
+                DeckExhaustedReason::PenetrationReached
+            );
+            assert_eq!(game.cards_left(), 52);
+        }
+
+        #[test]
+        fn recycle_discards() {
+            let mut game = game_with_policy(ReshufflePolicy::RecycleDiscards);
+            let (turns, outcome) = play_until_exhausted(&mut game);
+            assert_eq!(turns, 52);
+            // The three cards in the card history stay on the table
+            assert_eq!(
+                outcome.deck_exhausted,
+                Some(DeckExhausted {
+                    reason: DeckExhaustedReason::DiscardsRecycled,
+                    deck_size: 49,
+                })
+            );
+            assert_eq!(game.discards.len(), 3);
+
+            // The held back cards are recycled the next time round
+            let (turns, outcome) = play_until_exhausted(&mut game);
+            assert_eq!(turns, 49);
+            assert_eq!(outcome.deck_exhausted.unwrap().deck_size, 49);
+        }
+
+        #[test]
+        fn end_game() {
+            let mut game = game_with_policy(ReshufflePolicy::EndGame);
+            let (turns, outcome) = play_until_exhausted(&mut game);
+            assert_eq!(turns, 52);
+            assert_eq!(
+                outcome.deck_exhausted,
+                Some(DeckExhausted {
+                    reason: DeckExhaustedReason::GameEnded,
+                    deck_size: 0,
+                })
+            );
+            assert!(game.is_finished());
+
+            // Everyone leaving starts a new game
+            game.remove_player("sick boy");
+            assert!(!game.is_finished());
+            assert_eq!(game.cards_left(), 52);
+        }
+
+        #[test]
+        fn parse_policy() {
+            assert_eq!("immediate".parse(), Ok(ReshufflePolicy::Immediate));
+            assert_eq!("Recycle".parse(), Ok(ReshufflePolicy::RecycleDiscards));
+            assert_eq!("end".parse(), Ok(ReshufflePolicy::EndGame));
+            assert_eq!("penetration:60".parse(), Ok(ReshufflePolicy::Penetration(60)));
+            assert!("penetration:0".parse::<ReshufflePolicy>().is_err());
+            assert!("penetration".parse::<ReshufflePolicy>().is_err());
+            assert!("shuffle".parse::<ReshufflePolicy>().is_err());
+        }
+    }
+
+    mod game_length {
+        use super::*;
+        use red_or_black::settings::{GameLength, GameSettings, ReshufflePolicy};
+
+        fn game_with_length(game_length: GameLength) -> RedOrBlack {
+            let settings = GameSettings {
+                game_length,
+                ..GameSettings::default()
+            };
+            RedOrBlack::with_settings(vec!["mick".to_string(), "john".to_string()], settings)
+        }
+
+        #[test]
+        fn unlimited() {
+            let mut game = game_with_length(GameLength::Unlimited);
+            for _ in 0..200 {
+                game.play_turn(&CardColour::Red);
+            }
+            assert!(!game.is_finished());
+            assert_eq!(game.get_full_history().len(), 200);
+        }
+
+        #[test]
+        fn turns() {
+            let mut game = game_with_length(GameLength::Turns(5));
+            for _ in 0..4 {
+                game.play_turn(&CardColour::Red);
+                assert!(!game.is_finished());
+            }
+            game.play_turn(&CardColour::Red);
+            assert!(game.is_finished());
+
+            let stats = game.player_stats();
+            assert_eq!(stats.len(), 2);
+            assert_eq!(stats[0].username, "mick");
+            assert_eq!(stats[0].turns, 3);
+            assert_eq!(stats[1].turns, 2);
+        }
+
+        #[test]
+        fn minutes_end_without_a_turn() {
+            let mut game = game_with_length(GameLength::Minutes(2));
+            assert_eq!(game.deadline(), None);
+            game.play_turn(&CardColour::Red);
+            let deadline = game.deadline().unwrap();
+            assert!(!game.check_deadline(deadline - Duration::from_secs(1)));
+            assert!(!game.is_finished());
+            assert!(game.check_deadline(deadline));
+            assert!(game.is_finished());
+            // It only ends once
+            assert!(!game.check_deadline(deadline));
+        }
+
+        #[test]
+        fn one_deck_ignores_reshuffle_policy() {
+            let settings = GameSettings {
+                game_length: GameLength::OneDeck,
+                reshuffle: ReshufflePolicy::Immediate,
+                ..GameSettings::default()
+            };
+            let mut game = RedOrBlack::with_settings(vec!["mick".to_string()], settings);
+            for _ in 0..51 {
+                game.play_turn(&CardColour::Black);
+            }
+            assert!(!game.is_finished());
+            let outcome = game.play_turn(&CardColour::Black);
+            assert!(game.is_finished());
+            assert_eq!(
+                outcome.deck_exhausted.unwrap().reason,
+                DeckExhaustedReason::GameEnded
+            );
+        }
+
+        #[test]
+        fn parse_length() {
+            assert_eq!("unlimited".parse(), Ok(GameLength::Unlimited));
+            assert_eq!("deck".parse(), Ok(GameLength::OneDeck));
+            assert_eq!("turns:30".parse(), Ok(GameLength::Turns(30)));
+            assert_eq!("minutes:45".parse(), Ok(GameLength::Minutes(45)));
+            assert!("turns:0".parse::<GameLength>().is_err());
+            assert!("minutes".parse::<GameLength>().is_err());
+        }
+    }
+
+    mod everyone_plays {
+        use super::*;
+        use red_or_black::settings::{GameSettings, PlayMode};
+
+        fn game() -> RedOrBlack {
+            let settings = GameSettings {
+                play_mode: PlayMode::EveryonePlays(10),
+                ..GameSettings::default()
+            };
+            let usernames = vec!["mick".to_string(), "john".to_string(), "begbie".to_string()];
+            RedOrBlack::with_settings(usernames, settings)
+        }
+
+        // The right and wrong guess for the next card
+        fn guesses(game: &RedOrBlack) -> (CardColour, CardColour) {
+            if game.deck.peek().unwrap().suit.is_red() {
+                (CardColour::Red, CardColour::Black)
+            } else {
+                (CardColour::Black, CardColour::Red)
+            }
+        }
+
+        #[test]
+        fn first_guess_opens_the_window() {
+            let mut game = game();
+            let now = Instant::now();
+            assert_eq!(game.round_closes_at(), None);
+            assert_eq!(game.submit_guess("john", CardColour::Red, now), Ok(false));
+            assert_eq!(game.round_closes_at(), Some(now + Duration::from_secs(10)));
+            assert_eq!(game.waiting_for(), vec!["mick", "begbie"]);
+
+            let later = now + Duration::from_secs(5);
+            assert_eq!(game.submit_guess("mick", CardColour::Red, later), Ok(false));
+            assert_eq!(game.round_closes_at(), Some(now + Duration::from_secs(10)));
+            assert_eq!(game.submit_guess("begbie", CardColour::Black, later), Ok(true));
+        }
+
+        #[test]
+        fn one_guess_each() {
+            let mut game = game();
+            let now = Instant::now();
+            game.submit_guess("mick", CardColour::Red, now).unwrap();
+            assert_eq!(
+                game.submit_guess("mick", CardColour::Black, now),
+                Err(GuessError::AlreadyGuessed)
+            );
+            assert_eq!(
+                game.submit_guess("spud", CardColour::Black, now),
+                Err(GuessError::NotPlaying)
+            );
+        }
+
+        #[test]
+        fn wrong_players_take_the_penalty() {
+            let mut game = game();
+            let now = Instant::now();
+            let (right, wrong) = guesses(&game);
+            game.submit_guess("begbie", wrong, now).unwrap();
+            game.submit_guess("mick", right, now).unwrap();
+            let outcome = game.reveal().unwrap();
+
+            assert!(!outcome.everyone_right);
+            assert_eq!(outcome.penalty, 5);
+            let results: Vec<(&str, bool)> = outcome
+                .items
+                .iter()
+                .map(|i| (i.username.as_str(), i.outcome))
+                .collect();
+            // In seat order, and john sat this one out
+            assert_eq!(results, vec![("mick", true), ("begbie", false)]);
+            assert_eq!(game.get_penalty(), 5);
+            assert_eq!(game.turn_number(), 2);
+            assert_eq!(game.turns_played(), 1);
+            assert_eq!(game.get_full_history().len(), 2);
+            assert_eq!(game.round_closes_at(), None);
+            assert_eq!(game.waiting_for().len(), 3);
+        }
+
+        #[test]
+        fn bot_guesses_dont_open_the_window() {
+            let mut game = game();
+            assert_eq!(game.submit_bot_guess("john", CardColour::Red), Ok(false));
+            assert_eq!(game.round_closes_at(), None);
+            let now = Instant::now();
+            game.submit_guess("mick", CardColour::Red, now).unwrap();
+            assert_eq!(game.round_closes_at(), Some(now + Duration::from_secs(10)));
+        }
+
+        #[test]
+        fn players_that_dont_guess_count_as_wrong() {
+            let mut game = game();
+            let (right, _) = guesses(&game);
+            game.submit_guess("mick", right.clone(), Instant::now())
+                .unwrap();
+            game.submit_guess("john", right, Instant::now()).unwrap();
+            // Begbie ran out of time
+            let outcome = game.reveal().unwrap();
+            assert!(!outcome.everyone_right);
+            assert_eq!(game.get_penalty(), 5);
+        }
+
+        #[test]
+        fn penalty_grows_only_if_everyone_was_right() {
+            let mut game = game();
+            for round in 1..4 {
+                let (right, _) = guesses(&game);
+                for username in game.usernames().to_vec() {
+                    game.submit_guess(&username, right.clone(), Instant::now())
+                        .unwrap();
+                }
+                let outcome = game.reveal().unwrap();
+                assert!(outcome.everyone_right);
+                assert_eq!(game.get_penalty(), 5 + 5 * round);
+            }
+            assert_eq!(game.reveal(), None);
+        }
+
+        #[test]
+        fn leaving_can_finish_the_round() {
+            let mut game = game();
+            game.submit_guess("mick", CardColour::Red, Instant::now())
+                .unwrap();
+            game.submit_guess("john", CardColour::Red, Instant::now())
+                .unwrap();
+            assert!(!game.round_complete());
+            game.remove_player("begbie");
+            assert!(game.round_complete());
+
+            // The window closes if the only guess leaves with its player
+            game.remove_player("mick");
+            game.remove_player("john");
+            game.add_player("spud".to_string());
+            assert_eq!(game.round_closes_at(), None);
+        }
+
+        #[test]
+        fn rounds_are_restored_from_the_log() {
+            use red_or_black::events::read_events;
+
+            let dir = recording::temp_dir("rounds");
+            let _cleanup = recording::RemoveDir(dir.clone());
+            let mut game = game();
+            game.record_events(dir.clone());
+            for _ in 0..3 {
+                let (right, wrong) = guesses(&game);
+                game.submit_guess("mick", right, Instant::now()).unwrap();
+                game.submit_guess("john", wrong, Instant::now()).unwrap();
+                game.reveal().unwrap();
+            }
+            let restored = RedOrBlack::restore(game.settings().clone(), dir.clone())
+                .unwrap()
+                .unwrap();
+            let events = read_events(&dir, game.game_id()).unwrap();
+
+            assert_eq!(restored.get_full_history(), game.get_full_history());
+            assert_eq!(restored.deck, game.deck);
+            assert_eq!(restored.turns_played(), 3);
+            match events.last().unwrap().event {
+                GameEvent::RoundPlayed { ref items, .. } => assert_eq!(items.len(), 2),
+                ref e => panic!("Expected a round, got {:?}", e),
+            }
+        }
+
+        #[test]
+        fn parse_mode() {
+            assert_eq!("rotation".parse(), Ok(PlayMode::Rotation));
+            assert_eq!("everyone".parse(), Ok(PlayMode::EveryonePlays(15)));
+            assert_eq!("Everyone:30".parse(), Ok(PlayMode::EveryonePlays(30)));
+            assert!("everyone:0".parse::<PlayMode>().is_err());
+            assert!("teams".parse::<PlayMode>().is_err());
+        }
+    }
+
+    mod teams {
+        use super::*;
+        use red_or_black::settings::{GameSettings, PlayMode, TeamPenalty};
+
+        fn game(team_penalty: TeamPenalty) -> RedOrBlack {
+            let settings = GameSettings {
+                teams: 2,
+                team_penalty,
+                ..GameSettings::default()
+            };
+            let mut game = RedOrBlack::with_settings(vec!["mick".to_string()], settings);
+            for username in &["john", "begbie", "spud"] {
+                game.add_player(username.to_string());
+            }
+            game
+        }
+
+        #[test]
+        fn players_are_put_in_the_smallest_team() {
+            let game = game(TeamPenalty::Split);
+            assert_eq!(game.team_members(1), vec!["mick", "begbie"]);
+            assert_eq!(game.team_members(2), vec!["john", "spud"]);
+            assert_eq!(game.team_of("spud"), Some(2));
+        }
+
+        #[test]
+        fn turns_alternate_between_teams() {
+            let mut game = game(TeamPenalty::Split);
+            game.assign_team("spud", 1).unwrap();
+            let mut order = vec![game.get_current_player().unwrap().clone()];
+            for _ in 0..6 {
+                let outcome = game.play_turn(&CardColour::Red);
+                assert_eq!(outcome.item.team, game.team_of(&outcome.item.username));
+                order.push(outcome.next_player.unwrap());
+                assert_eq!(game.get_current_player(), order.last());
+            }
+            assert_eq!(
+                order,
+                vec!["mick", "john", "begbie", "john", "spud", "john", "mick"]
+            );
+        }
+
+        #[test]
+        fn turn_stays_with_the_team_when_its_player_leaves() {
+            let mut game = game(TeamPenalty::Split);
+            assert_eq!(game.get_current_player(), Some(&"mick".to_string()));
+            assert!(game.remove_player("mick"));
+            assert_eq!(game.get_current_player(), Some(&"begbie".to_string()));
+            assert_eq!(game.team_of("begbie"), Some(1));
+        }
+
+        #[test]
+        fn host_can_only_assign_real_teams() {
+            let mut game = game(TeamPenalty::Split);
+            assert_eq!(game.assign_team("mick", 3), Err(TeamError::NoSuchTeam));
+            assert_eq!(game.assign_team("mick", 0), Err(TeamError::NoSuchTeam));
+            assert_eq!(game.assign_team("sick boy", 1), Err(TeamError::NoSuchPlayer));
+
+            let mut solo = RedOrBlack::new(vec!["mick".to_string()]);
+            assert_eq!(solo.assign_team("mick", 1), Err(TeamError::TeamsOff));
+            assert_eq!(solo.team_of("mick"), None);
+        }
+
+        // Play until someone gets it wrong
+        fn wrong_guess(game: &mut RedOrBlack) -> TurnOutcome {
+            loop {
+                let outcome = game.play_turn(&CardColour::Red);
+                if !outcome.correct {
+                    return outcome;
+                }
+                assert!(outcome.shares.is_empty());
+            }
+        }
+
+        #[test]
+        fn penalties_are_split_by_house_rule() {
+            let mut split = game(TeamPenalty::Split);
+            let outcome = wrong_guess(&mut split);
+            let team = outcome.item.team.unwrap();
+            let drinkers: Vec<String> = outcome.shares.iter().map(|s| s.username.clone()).collect();
+            assert_eq!(drinkers, split.team_members(team));
+            for share in &outcome.shares {
+                assert_eq!(share.seconds, outcome.penalty.div_ceil(2));
+            }
+
+            let mut guesser = game(TeamPenalty::Guesser);
+            let outcome = wrong_guess(&mut guesser);
+            assert_eq!(
+                outcome.shares,
+                vec![PenaltyShare {
+                    username: outcome.item.username.clone(),
+                    seconds: outcome.penalty,
+                }]
+            );
+        }
+
+        #[test]
+        fn scores_are_kept_per_team() {
+            let mut game = game(TeamPenalty::Split);
+            for _ in 0..20 {
+                game.play_turn(&CardColour::Black);
+            }
+            let scores = game.team_stats();
+            assert_eq!(scores.len(), 2);
+            assert_eq!(scores[0].players, vec!["mick", "begbie"]);
+            assert_eq!(scores[0].turns + scores[1].turns, 20);
+            let penalties: u32 = game
+                .get_full_history()
+                .iter()
+                .filter(|i| !i.outcome)
+                .map(|i| u32::from(i.penalty))
+                .sum();
+            assert_eq!(scores[0].penalty + scores[1].penalty, penalties);
+        }
+
+        #[test]
+        fn teams_are_restored_from_the_log() {
+            let dir = recording::temp_dir("teams");
+            let _cleanup = recording::RemoveDir(dir.clone());
+            let mut game = game(TeamPenalty::Split);
+            game.record_events(dir.clone());
+            game.assign_team("mick", 2).unwrap();
+            for _ in 0..5 {
+                game.play_turn(&CardColour::Red);
+            }
+            let mut restored = RedOrBlack::restore(game.settings().clone(), dir.clone())
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(restored.team_members(1), vec!["begbie"]);
+            assert_eq!(restored.team_members(2), vec!["mick", "john", "spud"]);
+            assert_eq!(restored.get_current_player(), game.get_current_player());
+        }
+
+        #[test]
+        fn wrong_guesses_are_shared_when_everyone_plays() {
+            let settings = GameSettings {
+                play_mode: PlayMode::EveryonePlays(10),
+                teams: 2,
+                team_penalty: TeamPenalty::Split,
+                ..GameSettings::default()
+            };
+            let usernames = ["mick", "john", "begbie", "spud"];
+            let mut game = RedOrBlack::with_settings(
+                usernames.iter().map(|u| u.to_string()).collect(),
+                settings,
+            );
+            let wrong = if game.deck.peek().unwrap().suit.is_red() {
+                CardColour::Black
+            } else {
+                CardColour::Red
+            };
+            // Mick and begbie are both in team 1, john is in team 2
+            for username in &["mick", "begbie", "john"] {
+                game.submit_guess(username, wrong.clone(), Instant::now())
+                    .unwrap();
+            }
+            let outcome = game.reveal().unwrap();
+            let shares: Vec<(&str, u16)> = outcome
+                .shares
+                .iter()
+                .map(|s| (s.username.as_str(), s.seconds))
+                .collect();
+            assert_eq!(shares, vec![("mick", 6), ("begbie", 6), ("john", 3), ("spud", 3)]);
+        }
+
+        #[test]
+        fn parse_team_penalty() {
+            assert_eq!("split".parse(), Ok(TeamPenalty::Split));
+            assert_eq!("Guesser".parse(), Ok(TeamPenalty::Guesser));
+            assert!("captain".parse::<TeamPenalty>().is_err());
+        }
+    }
+
+    mod recording {
+        use super::*;
+        use red_or_black::events::read_events;
+        use std::env;
+        use std::fs;
+        use std::process;
+
+        #[test]
+        fn events_are_logged() {
+            let dir = env::temp_dir().join(format!("red_or_black_recording_{}", process::id()));
+            let mut game = RedOrBlack::new(vec!["mick".to_string()]);
+            game.record_events(dir.clone());
+            let first_deck = game.deck.cards().to_vec();
+            game.add_player("john".to_string());
+            let outcome = game.play_turn(&CardColour::Red);
+            game.remove_player("john");
+
+            let events: Vec<GameEvent> = read_events(&dir, game.game_id())
+                .unwrap()
+                .into_iter()
+                .map(|logged| logged.event)
+                .collect();
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(events.len(), 5);
+            assert_eq!(
+                events[0],
+                GameEvent::DeckShuffled {
+                    cards: first_deck,
+                    reason: None,
+                }
+            );
+            assert_eq!(
+                events[1],
+                GameEvent::PlayerJoined {
+                    username: "mick".to_string(),
+                }
+            );
+            match events[3] {
+                GameEvent::TurnPlayed {
+                    ref item,
+                    ref next_player,
+                    cards_left,
+                } => {
+                    assert_eq!(item.card, outcome.card);
+                    assert_eq!(next_player, &Some("john".to_string()));
+                    assert_eq!(cards_left, 51);
+                }
+                ref e => panic!("Expected a turn, got {:?}", e),
+            }
+            assert_eq!(
+                events[4],
+                GameEvent::PlayerLeft {
+                    username: "john".to_string(),
+                }
+            );
+        }
+
+        pub fn temp_dir(name: &str) -> PathBuf {
+            env::temp_dir().join(format!("red_or_black_{}_{}", name, process::id()))
+        }
+
+        // Removes a test's directory when it goes out of scope, so it's cleaned up even if an
+        // assert fails
+        pub struct RemoveDir(pub PathBuf);
+
+        impl Drop for RemoveDir {
+            fn drop(&mut self) {
+                let _ = fs::remove_dir_all(&self.0);
+            }
+        }
+
+        #[test]
+        fn game_is_restored_from_the_log() {
+            use red_or_black::settings::ReshufflePolicy;
+
+            let dir = temp_dir("restore");
+            let settings = GameSettings {
+                reshuffle: ReshufflePolicy::Penetration(10),
+                ..GameSettings::default()
+            };
+            let mut game = RedOrBlack::with_settings(vec![], settings.clone());
+            game.record_events(dir.clone());
+            game.add_player("mick".to_string());
+            game.add_player("john".to_string());
+            game.add_player("begbie".to_string());
+            for _ in 0..12 {
+                game.play_turn(&CardColour::Red);
+            }
+            game.remove_player("begbie");
+            game.play_turn(&CardColour::Black);
+
+            let mut restored = RedOrBlack::restore(settings, dir.clone())
+                .unwrap()
+                .unwrap();
+            // Turns played after restoring go in the same log
+            restored.play_turn(&CardColour::Black);
+            game.deck.pop();
+            let events = read_events(&dir, game.game_id()).unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(restored.game_id(), game.game_id());
+            assert_eq!(restored.usernames, game.usernames);
+            assert_eq!(restored.get_current_player(), Some(&"mick".to_string()));
+            assert_eq!(restored.get_full_history()[..13], game.get_full_history()[..]);
+            assert_eq!(restored.deck, game.deck);
+            assert_eq!(restored.deck_size, game.deck_size);
+            assert_eq!(restored.turn_number, 15);
+            match events.last().unwrap().event {
+                GameEvent::TurnPlayed { ref item, .. } => assert_eq!(item.turn_number, 14),
+                ref e => panic!("Expected a turn, got {:?}", e),
+            }
+        }
+
+        #[test]
+        fn reset_games_are_not_restored() {
+            let dir = temp_dir("reset");
+            let mut game = RedOrBlack::new(vec!["mick".to_string()]);
+            game.record_events(dir.clone());
+            game.play_turn(&CardColour::Red);
+            game.remove_player("mick");
+
+            let restored = RedOrBlack::restore(GameSettings::default(), dir.clone()).unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+            assert!(restored.is_none());
+        }
+
+        #[test]
+        fn finished_games_are_not_restored() {
+            let dir = temp_dir("finished");
+            let settings = GameSettings {
+                game_length: GameLength::Turns(1),
+                ..GameSettings::default()
+            };
+            let mut game = RedOrBlack::with_settings(vec!["mick".to_string()], settings.clone());
+            game.record_events(dir.clone());
+            game.play_turn(&CardColour::Red);
+            assert!(game.is_finished());
+
+            let restored = RedOrBlack::restore(settings, dir.clone()).unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+            assert!(restored.is_none());
+        }
+
+        #[test]
+        fn players_reclaim_their_seats() {
+            let dir = temp_dir("reclaim");
+            let mut game = RedOrBlack::new(vec!["mick".to_string(), "john".to_string()]);
+            game.record_events(dir.clone());
+            game.play_turn(&CardColour::Red);
+
+            let mut restored = RedOrBlack::restore(GameSettings::default(), dir.clone())
+                .unwrap()
+                .unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+            assert_eq!(restored.get_current_player(), Some(&"john".to_string()));
+
+            restored.add_player("mick".to_string());
+            assert_eq!(restored.usernames, vec!["mick", "john"]);
+            assert!(!restored.expire_unclaimed_players(Duration::from_secs(60)));
+
+            // John never came back
+            assert!(restored.expire_unclaimed_players(Duration::from_secs(0)));
+            assert_eq!(restored.usernames, vec!["mick"]);
+            assert_eq!(restored.get_current_player(), Some(&"mick".to_string()));
+        }
+
+        #[test]
+        fn each_game_gets_a_new_id() {
+            let mut game = RedOrBlack::new(vec!["mick".to_string()]);
+            let first_id = game.game_id().to_string();
+            game.remove_player("mick");
+            assert_ne!(game.game_id(), first_id);
+        }
+    }
+
     #[test]
     fn validate_guess() {
         use deck::{Card, Suit, Value};
         use red_or_black::messages::CardColour;
 
         let game = RedOrBlack::new(vec!["mick".to_string()]);
-        assert_eq!(
+        assert!(
             game.validate_guess(
                 &CardColour::Red,
                 Card {
                     value: Value::Ace,
                     suit: Suit::Heart,
                 }
-            ),
-            true
+            )
         );
 
-        assert_eq!(
-            game.validate_guess(
+        assert!(
+            !game.validate_guess(
                 &CardColour::Black,
                 Card {
                     value: Value::Ace,
                     suit: Suit::Diamond,
                 }
-            ),
-            false
+            )
         );
 
-        assert_eq!(
-            game.validate_guess(
+        assert!(
+            !game.validate_guess(
                 &CardColour::Red,
                 Card {
                     value: Value::Ace,
                     suit: Suit::Spade,
                 }
-            ),
-            false
+            )
         );
 
-        assert_eq!(
+        assert!(
             game.validate_guess(
                 &CardColour::Black,
                 Card {
                     value: Value::Ace,
                     suit: Suit::Club,
                 }
-            ),
-            true
+            )
         );
     }
 }
@@ -0,0 +1,225 @@
+use super::messages::{ReceivableMessage, SendableMessage};
+use super::protocol::Envelope;
+use schemars::gen::SchemaSettings;
+use schemars::schema::{
+    InstanceType, Metadata, RootSchema, Schema, SchemaObject, SingleOrVec, SubschemaValidation,
+};
+use serde_json;
+
+// Where the generated files are kept, relative to the root of the crate
+pub const SCHEMA_FILE: &str = "schema/protocol.schema.json";
+pub const TYPESCRIPT_FILE: &str = "schema/protocol.d.ts";
+
+const GENERATED_BY: &str = "Generated by `cargo run --bin red_or_black_schema`, don't edit by hand";
+
+const DEFINITIONS: &str = "#/definitions/";
+
+// Every message that can be sent to or from the server, along with the types they use
+pub fn protocol_schema() -> RootSchema {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    let envelope = gen.subschema_for::<Envelope>();
+    let receivable = gen.subschema_for::<ReceivableMessage>();
+    let sendable = gen.subschema_for::<SendableMessage>();
+    let schema = SchemaObject {
+        metadata: Some(Box::new(Metadata {
+            title: Some("Red or Black protocol".to_string()),
+            description: Some(format!(
+                "Messages are wrapped in an Envelope, with the name of a ReceivableMessage or \
+                 SendableMessage as its type and the message's fields as its payload. The bare \
+                 messages are the legacy format. {}.",
+                GENERATED_BY
+            )),
+            ..Metadata::default()
+        })),
+        subschemas: Some(Box::new(SubschemaValidation {
+            one_of: Some(vec![envelope, receivable, sendable]),
+            ..SubschemaValidation::default()
+        })),
+        ..SchemaObject::default()
+    };
+    RootSchema {
+        meta_schema: gen.settings().meta_schema.clone(),
+        schema,
+        definitions: gen.take_definitions(),
+    }
+}
+
+pub fn json_schema() -> String {
+    let mut json = serde_json::to_string_pretty(&protocol_schema()).unwrap();
+    json.push('\n');
+    json
+}
+
+// Unions and intersections need brackets around them when they're nested in another type
+fn bracketed(t: String) -> String {
+    if t.contains(' ') && !t.starts_with('{') {
+        format!("({})", t)
+    } else {
+        t
+    }
+}
+
+fn join(types: Vec<String>, separator: &str) -> String {
+    if types.len() == 1 {
+        return types.into_iter().next().unwrap();
+    }
+    types
+        .into_iter()
+        .map(bracketed)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn ts_schema(schema: &Schema) -> String {
+    match *schema {
+        Schema::Bool(true) => "unknown".to_string(),
+        Schema::Bool(false) => "never".to_string(),
+        Schema::Object(ref object) => ts_object(object),
+    }
+}
+
+fn ts_instance(object: &SchemaObject, instance: InstanceType) -> String {
+    match instance {
+        InstanceType::Null => "null".to_string(),
+        InstanceType::Boolean => "boolean".to_string(),
+        InstanceType::Integer | InstanceType::Number => "number".to_string(),
+        InstanceType::String => "string".to_string(),
+        InstanceType::Array => match object.array.as_ref().and_then(|a| a.items.as_ref()) {
+            Some(SingleOrVec::Single(ref item)) => format!("{}[]", bracketed(ts_schema(item))),
+            Some(SingleOrVec::Vec(ref items)) => format!(
+                "[{}]",
+                items.iter().map(ts_schema).collect::<Vec<_>>().join(", ")
+            ),
+            None => "unknown[]".to_string(),
+        },
+        InstanceType::Object => {
+            let validation = match object.object {
+                Some(ref validation) => validation,
+                None => return "{ [key: string]: unknown }".to_string(),
+            };
+            let mut fields: Vec<String> = validation
+                .properties
+                .iter()
+                .map(|(name, schema)| {
+                    let optional = if validation.required.contains(name) {
+                        ""
+                    } else {
+                        "?"
+                    };
+                    format!("{}{}: {}", name, optional, ts_schema(schema))
+                })
+                .collect();
+            if let Some(ref additional) = validation.additional_properties {
+                if **additional != Schema::Bool(false) {
+                    fields.push(format!("[key: string]: {}", ts_schema(additional)));
+                }
+            }
+            format!("{{ {} }}", fields.join("; "))
+        }
+    }
+}
+
+// The TypeScript type for a schema, following the same rules serde uses for the JSON
+fn ts_object(object: &SchemaObject) -> String {
+    if let Some(ref reference) = object.reference {
+        return reference.trim_start_matches(DEFINITIONS).to_string();
+    }
+    if let Some(ref value) = object.const_value {
+        return value.to_string();
+    }
+    if let Some(ref values) = object.enum_values {
+        return join(values.iter().map(|v| v.to_string()).collect(), " | ");
+    }
+
+    let mut parts = Vec::new();
+    match object.instance_type {
+        Some(SingleOrVec::Single(ref instance)) => parts.push(ts_instance(object, **instance)),
+        Some(SingleOrVec::Vec(ref instances)) => parts.push(join(
+            instances.iter().map(|i| ts_instance(object, *i)).collect(),
+            " | ",
+        )),
+        None => {}
+    }
+    let mut alternatives = Vec::new();
+    if let Some(ref subschemas) = object.subschemas {
+        if let Some(ref all_of) = subschemas.all_of {
+            parts.extend(all_of.iter().map(ts_schema));
+        }
+        for any in subschemas.one_of.iter().chain(subschemas.any_of.iter()) {
+            alternatives.extend(any.iter().map(ts_schema));
+        }
+    }
+    match (parts.is_empty(), alternatives.is_empty()) {
+        (true, true) => "unknown".to_string(),
+        (false, true) => join(parts, " & "),
+        (true, false) => join(alternatives, " | "),
+        (false, false) => format!("{} & ({})", join(parts, " & "), join(alternatives, " | ")),
+    }
+}
+
+// Unions of messages are written one per line to keep them readable
+fn ts_definition(name: &str, schema: &Schema) -> String {
+    if let Schema::Object(ref object) = *schema {
+        let alternatives = object
+            .subschemas
+            .as_ref()
+            .and_then(|s| s.one_of.as_ref().or(s.any_of.as_ref()));
+        if let (None, Some(alternatives)) = (object.instance_type.as_ref(), alternatives) {
+            let lines: Vec<String> = alternatives
+                .iter()
+                .map(|a| format!("  | {}", ts_schema(a)))
+                .collect();
+            return format!("export type {} =\n{};\n", name, lines.join("\n"));
+        }
+    }
+    format!("export type {} = {};\n", name, ts_schema(schema))
+}
+
+pub fn typescript() -> String {
+    let schema = protocol_schema();
+    let mut ts = format!("// {}\n", GENERATED_BY);
+    for (name, definition) in &schema.definitions {
+        ts.push('\n');
+        ts.push_str(&ts_definition(name, definition));
+    }
+    ts
+}
+
+#[cfg(test)]
+mod generated_files {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn check_up_to_date(file: &str, generated: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(file);
+        let checked_in = fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            checked_in == generated,
+            "{} is out of date, run `cargo run --bin red_or_black_schema` to update it",
+            file
+        );
+    }
+
+    #[test]
+    fn schema_is_up_to_date() {
+        check_up_to_date(SCHEMA_FILE, &json_schema());
+    }
+
+    #[test]
+    fn typescript_is_up_to_date() {
+        check_up_to_date(TYPESCRIPT_FILE, &typescript());
+    }
+
+    #[test]
+    fn follows_the_serde_tagging() {
+        let ts = typescript();
+        // Messages from the server are tagged with msg_type
+        assert!(ts.contains("{ msg_type: \"LoggedIn\" }"));
+        assert!(ts.contains("{ details?: PlayerInfo[]; msg_type: \"Players\"; players: string[] }"));
+        // Messages to the server are externally tagged, and unit variants are just the name
+        assert!(ts.contains("{ Chat: { text: string } }"));
+        assert!(ts.contains("\"ListRooms\""));
+        assert!(ts.contains("export type Card = { suit: Suit; value: Value };"));
+    }
+}
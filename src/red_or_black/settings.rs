@@ -0,0 +1,174 @@
+use schemars::JsonSchema;
+use std::str::FromStr;
+
+// How much help the players get with counting the cards left in the deck
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum CardCounting {
+    Off,
+    Colours,
+    Values,
+}
+
+impl FromStr for CardCounting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(CardCounting::Off),
+            "colours" | "colors" => Ok(CardCounting::Colours),
+            "values" => Ok(CardCounting::Values),
+            _ => Err(format!("Unknown card counting setting '{}'", s)),
+        }
+    }
+}
+
+// What to do when the deck runs out (or is running low)
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum ReshufflePolicy {
+    // Start a new shuffled deck as soon as the last card is drawn
+    Immediate,
+    // Start a new shuffled deck once this percentage of the deck has been dealt
+    Penetration(u8),
+    // Shuffle the dealt cards back into a new deck, except the ones still showing on the table
+    RecycleDiscards,
+    // The game is over once the deck runs out
+    EndGame,
+}
+
+impl FromStr for ReshufflePolicy {
+    type Err = String;
+
+    // Penetration is given as "penetration:<percent>", e.g. "penetration:75"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let mut parts = lower.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("immediate"), None) => Ok(ReshufflePolicy::Immediate),
+            (Some("recycle"), None) => Ok(ReshufflePolicy::RecycleDiscards),
+            (Some("end"), None) => Ok(ReshufflePolicy::EndGame),
+            (Some("penetration"), Some(percent)) => match percent.parse::<u8>() {
+                Ok(p) if p > 0 && p <= 100 => Ok(ReshufflePolicy::Penetration(p)),
+                _ => Err(format!(
+                    "Penetration must be between 1 and 100, got '{}'",
+                    percent
+                )),
+            },
+            _ => Err(format!("Unknown reshuffle policy '{}'", s)),
+        }
+    }
+}
+
+// How long a game lasts before the game is over and the summary is sent out
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum GameLength {
+    Unlimited,
+    // The game is over when the first deck runs out, whatever the reshuffle policy
+    OneDeck,
+    Turns(u16),
+    // Counted from the first turn. The game ends when the time is up, even if nobody is playing.
+    Minutes(u64),
+}
+
+impl FromStr for GameLength {
+    type Err = String;
+
+    // Given as "unlimited", "deck", "turns:<n>" or "minutes:<n>"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let mut parts = lower.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("unlimited"), None) => Ok(GameLength::Unlimited),
+            (Some("deck"), None) => Ok(GameLength::OneDeck),
+            (Some("turns"), Some(n)) => match n.parse::<u16>() {
+                Ok(n) if n > 0 => Ok(GameLength::Turns(n)),
+                _ => Err(format!("Invalid number of turns '{}'", n)),
+            },
+            (Some("minutes"), Some(n)) => match n.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(GameLength::Minutes(n)),
+                _ => Err(format!("Invalid number of minutes '{}'", n)),
+            },
+            _ => Err(format!("Unknown game length '{}'", s)),
+        }
+    }
+}
+
+// Who guesses each card
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum PlayMode {
+    // The players take it in turns, one guess per card
+    #[default]
+    Rotation,
+    // Everyone guesses every card. The card is revealed once everyone has guessed, or this many
+    // seconds after the first guess.
+    EveryonePlays(u64),
+}
+
+pub const DEFAULT_GUESS_WINDOW_SECS: u64 = 15;
+
+impl FromStr for PlayMode {
+    type Err = String;
+
+    // Given as "rotation", "everyone" or "everyone:<seconds>"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let mut parts = lower.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("rotation"), None) => Ok(PlayMode::Rotation),
+            (Some("everyone"), None) => Ok(PlayMode::EveryonePlays(DEFAULT_GUESS_WINDOW_SECS)),
+            (Some("everyone"), Some(n)) => match n.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(PlayMode::EveryonePlays(n)),
+                _ => Err(format!("Invalid guess window '{}'", n)),
+            },
+            _ => Err(format!("Unknown play mode '{}'", s)),
+        }
+    }
+}
+
+// Who drinks when someone in a team gets it wrong
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum TeamPenalty {
+    // The penalty is split between everyone in the team, rounded up
+    #[default]
+    Split,
+    // The player that guessed takes all of it
+    Guesser,
+}
+
+impl FromStr for TeamPenalty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "split" => Ok(TeamPenalty::Split),
+            "guesser" => Ok(TeamPenalty::Guesser),
+            _ => Err(format!("Unknown team penalty '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct GameSettings {
+    pub card_counting: CardCounting,
+    pub reshuffle: ReshufflePolicy,
+    pub game_length: GameLength,
+    #[serde(default)]
+    pub play_mode: PlayMode,
+    // The number of teams the players are split into, 0 to play without teams
+    #[serde(default)]
+    pub teams: u8,
+    #[serde(default)]
+    pub team_penalty: TeamPenalty,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            card_counting: CardCounting::Off,
+            reshuffle: ReshufflePolicy::Immediate,
+            game_length: GameLength::Unlimited,
+            play_mode: PlayMode::Rotation,
+            teams: 0,
+            team_penalty: TeamPenalty::Split,
+        }
+    }
+}
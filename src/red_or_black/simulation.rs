@@ -0,0 +1,342 @@
+use super::bots::BotStrategy;
+use super::rules::{RedOrBlack, TurnOutcome};
+use super::settings::{GameLength, GameSettings};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// The widest bar in the text report's charts
+const BAR_WIDTH: u64 = 40;
+
+pub struct SimulationSettings {
+    pub games: usize,
+    // How the player in each seat guesses, in turn order
+    pub strategies: Vec<BotStrategy>,
+    pub game_settings: GameSettings,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SimulationError {
+    NoPlayers,
+    // Games have to end by themselves, and time limits don't mean anything when nobody waits
+    NeverEnds,
+}
+
+impl SimulationError {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            SimulationError::NoPlayers => "There has to be at least one player",
+            SimulationError::NeverEnds => {
+                "Games have to be limited to one deck or a number of turns"
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SeatReport {
+    // Seats are numbered from 1, in turn order
+    pub seat: usize,
+    pub strategy: BotStrategy,
+    pub turns: u64,
+    pub correct: u64,
+    pub correct_percentage: f64,
+    // How many times the player had to drink, and for how many seconds in total
+    pub penalties: u64,
+    pub penalty_served: u64,
+    pub penalty_served_per_game: f64,
+    pub biggest_penalty: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub games: usize,
+    pub turns: u64,
+    pub turns_per_game: f64,
+    // How many times each penalty was served
+    pub penalties: BTreeMap<u16, u64>,
+    pub mean_penalty: f64,
+    pub median_penalty: u16,
+    pub biggest_penalty: u16,
+    // How many times the table got each number of guesses right in a row, counting the run
+    // that is going when a game ends
+    pub streaks: BTreeMap<u32, u64>,
+    pub longest_streak: u32,
+    pub reshuffles: u64,
+    pub reshuffles_per_game: f64,
+    // None if the deck never ran out
+    pub turns_per_reshuffle: Option<f64>,
+    pub seats: Vec<SeatReport>,
+    // The standard deviation of the penalty served by each seat divided by the mean, 0 means
+    // every seat drank the same amount
+    pub penalty_spread: f64,
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+impl Report {
+    fn new(strategies: &[BotStrategy], games: usize) -> Self {
+        Report {
+            games,
+            turns: 0,
+            turns_per_game: 0.0,
+            penalties: BTreeMap::new(),
+            mean_penalty: 0.0,
+            median_penalty: 0,
+            biggest_penalty: 0,
+            streaks: BTreeMap::new(),
+            longest_streak: 0,
+            reshuffles: 0,
+            reshuffles_per_game: 0.0,
+            turns_per_reshuffle: None,
+            seats: strategies
+                .iter()
+                .enumerate()
+                .map(|(i, strategy)| SeatReport {
+                    seat: i + 1,
+                    strategy: *strategy,
+                    turns: 0,
+                    correct: 0,
+                    correct_percentage: 0.0,
+                    penalties: 0,
+                    penalty_served: 0,
+                    penalty_served_per_game: 0.0,
+                    biggest_penalty: 0,
+                })
+                .collect(),
+            penalty_spread: 0.0,
+        }
+    }
+
+    fn record_turn(&mut self, seat: usize, outcome: &TurnOutcome) {
+        self.turns += 1;
+        if outcome.deck_exhausted.is_some() {
+            self.reshuffles += 1;
+        }
+        let seat = &mut self.seats[seat];
+        seat.turns += 1;
+        if outcome.correct {
+            seat.correct += 1;
+        } else {
+            seat.penalties += 1;
+            seat.penalty_served += u64::from(outcome.penalty);
+            seat.biggest_penalty = seat.biggest_penalty.max(outcome.penalty);
+            *self.penalties.entry(outcome.penalty).or_default() += 1;
+        }
+    }
+
+    fn record_streak(&mut self, streak: u32) {
+        *self.streaks.entry(streak).or_default() += 1;
+    }
+
+    // Work out the averages once every game has been played
+    fn finish(&mut self) {
+        let games = self.games as u64;
+        self.turns_per_game = ratio(self.turns, games);
+        self.reshuffles_per_game = ratio(self.reshuffles, games);
+        if self.reshuffles > 0 {
+            self.turns_per_reshuffle = Some(ratio(self.turns, self.reshuffles));
+        }
+
+        let served: u64 = self.penalties.iter().map(|(p, n)| u64::from(*p) * n).sum();
+        let count: u64 = self.penalties.values().sum();
+        self.mean_penalty = ratio(served, count);
+        self.biggest_penalty = self.penalties.keys().next_back().cloned().unwrap_or(0);
+        let mut seen = 0;
+        for (penalty, n) in &self.penalties {
+            seen += n;
+            if seen * 2 >= count {
+                self.median_penalty = *penalty;
+                break;
+            }
+        }
+        self.longest_streak = self.streaks.keys().next_back().cloned().unwrap_or(0);
+
+        for seat in &mut self.seats {
+            seat.correct_percentage = ratio(seat.correct * 100, seat.turns);
+            seat.penalty_served_per_game = ratio(seat.penalty_served, games);
+        }
+        let mean = ratio(served, self.seats.len() as u64);
+        if mean > 0.0 {
+            let variance = self
+                .seats
+                .iter()
+                .map(|s| (s.penalty_served as f64 - mean).powi(2))
+                .sum::<f64>()
+                / self.seats.len() as f64;
+            self.penalty_spread = variance.sqrt() / mean;
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        writeln!(
+            text,
+            "Simulated {} games, {} turns ({:.1} per game)",
+            self.games, self.turns, self.turns_per_game
+        )
+        .unwrap();
+
+        writeln!(text, "\nPenalties served (seconds)").unwrap();
+        writeln!(
+            text,
+            "  mean {:.1}, median {}, biggest {}",
+            self.mean_penalty, self.median_penalty, self.biggest_penalty
+        )
+        .unwrap();
+        write_chart(&mut text, &self.penalties);
+
+        writeln!(text, "\nCorrect guesses in a row").unwrap();
+        writeln!(text, "  longest {}", self.longest_streak).unwrap();
+        write_chart(&mut text, &self.streaks);
+
+        writeln!(text, "\nReshuffles").unwrap();
+        match self.turns_per_reshuffle {
+            Some(turns) => writeln!(
+                text,
+                "  {:.2} per game, one every {:.1} turns",
+                self.reshuffles_per_game, turns
+            ),
+            None => writeln!(text, "  the deck never ran out"),
+        }
+        .unwrap();
+
+        writeln!(text, "\nSeats").unwrap();
+        writeln!(
+            text,
+            "  {:<5} {:<10} {:>8} {:>8} {:>9} {:>10} {:>8} {:>8}",
+            "seat", "strategy", "turns", "correct", "penalties", "served", "per game", "biggest"
+        )
+        .unwrap();
+        for seat in &self.seats {
+            writeln!(
+                text,
+                "  {:<5} {:<10} {:>8} {:>7.1}% {:>9} {:>10} {:>8.1} {:>8}",
+                seat.seat,
+                format!("{:?}", seat.strategy),
+                seat.turns,
+                seat.correct_percentage,
+                seat.penalties,
+                seat.penalty_served,
+                seat.penalty_served_per_game,
+                seat.biggest_penalty
+            )
+            .unwrap();
+        }
+        writeln!(
+            text,
+            "  penalty served varies by {:.1}% between seats",
+            self.penalty_spread * 100.0
+        )
+        .unwrap();
+        text
+    }
+}
+
+// One line per value, with a bar scaled to the most common value
+fn write_chart<K: ToString>(text: &mut String, counts: &BTreeMap<K, u64>) {
+    let most = counts.values().cloned().max().unwrap_or(0);
+    for (value, n) in counts {
+        let bar = "#".repeat((n * BAR_WIDTH / most.max(1)) as usize);
+        writeln!(text, "  {:>6} {:>9} {}", value.to_string(), n, bar).unwrap();
+    }
+}
+
+// Play the games with every seat guessing by its strategy
+pub fn simulate(settings: &SimulationSettings) -> Result<Report, SimulationError> {
+    if settings.strategies.is_empty() {
+        return Err(SimulationError::NoPlayers);
+    }
+    match settings.game_settings.game_length {
+        GameLength::Unlimited | GameLength::Minutes(_) => return Err(SimulationError::NeverEnds),
+        GameLength::OneDeck | GameLength::Turns(_) => {}
+    }
+
+    let usernames: Vec<String> = (1..=settings.strategies.len())
+        .map(|i| format!("Seat {}", i))
+        .collect();
+    let mut report = Report::new(&settings.strategies, settings.games);
+    for _ in 0..settings.games {
+        let mut game = RedOrBlack::with_settings(usernames.clone(), settings.game_settings.clone());
+        let mut streak = 0;
+        while !game.is_finished() {
+            let seat = game
+                .get_current_player()
+                .and_then(|p| usernames.iter().position(|u| u == p))
+                .unwrap_or(0);
+            let guess = settings.strategies[seat].guess(&game);
+            let outcome = game.play_turn(&guess);
+            report.record_turn(seat, &outcome);
+            if outcome.correct {
+                streak += 1;
+            } else {
+                report.record_streak(streak);
+                streak = 0;
+            }
+        }
+        report.record_streak(streak);
+    }
+    report.finish();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod simulate {
+    use super::*;
+    use red_or_black::settings::ReshufflePolicy;
+
+    fn settings(strategies: Vec<BotStrategy>, game_length: GameLength) -> SimulationSettings {
+        SimulationSettings {
+            games: 50,
+            strategies,
+            game_settings: GameSettings {
+                game_length,
+                ..GameSettings::default()
+            },
+        }
+    }
+
+    #[test]
+    fn games_have_to_end() {
+        let unlimited = settings(vec![BotStrategy::Random], GameLength::Unlimited);
+        assert_eq!(simulate(&unlimited), Err(SimulationError::NeverEnds));
+        let nobody = settings(Vec::new(), GameLength::OneDeck);
+        assert_eq!(simulate(&nobody), Err(SimulationError::NoPlayers));
+    }
+
+    #[test]
+    fn one_deck_games() {
+        let strategies = vec![BotStrategy::AlwaysRed, BotStrategy::Majority];
+        let report = simulate(&settings(strategies, GameLength::OneDeck)).unwrap();
+        assert_eq!(report.turns, 50 * 52);
+        assert_eq!(report.turns_per_game, 52.0);
+        assert_eq!(report.reshuffles, 50);
+        assert_eq!(report.turns_per_reshuffle, Some(52.0));
+        // The seats take turns
+        assert_eq!(report.seats[0].turns, 50 * 26);
+        assert_eq!(report.seats[1].turns, 50 * 26);
+
+        let wrong: u64 = report.seats.iter().map(|s| s.penalties).sum();
+        assert_eq!(report.penalties.values().sum::<u64>(), wrong);
+        // Every wrong guess ends a streak, and so does the end of each game
+        assert_eq!(report.streaks.values().sum::<u64>(), wrong + 50);
+        assert!(report.penalties.keys().all(|p| p % 5 == 0));
+    }
+
+    #[test]
+    fn turn_limited_games() {
+        let mut settings = settings(vec![BotStrategy::Random; 3], GameLength::Turns(10));
+        settings.game_settings.reshuffle = ReshufflePolicy::Immediate;
+        let report = simulate(&settings).unwrap();
+        assert_eq!(report.turns, 50 * 10);
+        assert_eq!(report.reshuffles, 0);
+        assert_eq!(report.turns_per_reshuffle, None);
+        assert!(report.penalty_spread >= 0.0);
+        assert!(report.to_text().contains("the deck never ran out"));
+    }
+}
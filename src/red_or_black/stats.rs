@@ -0,0 +1,212 @@
+use super::history::HistoryItem;
+use super::messages::CardColour;
+use schemars::JsonSchema;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PlayerStats {
+    pub username: String,
+    pub turns: u32,
+    pub correct: u32,
+    pub correct_percentage: f64,
+    pub longest_streak: u32,
+    // Total seconds spent drinking, i.e. the sum of the penalties for wrong guesses
+    pub penalty_served: u32,
+    pub biggest_penalty: u16,
+    pub red_guesses: u32,
+    pub black_guesses: u32,
+}
+
+impl PlayerStats {
+    fn new(username: String) -> Self {
+        PlayerStats {
+            username,
+            turns: 0,
+            correct: 0,
+            correct_percentage: 0.0,
+            longest_streak: 0,
+            penalty_served: 0,
+            biggest_penalty: 0,
+            red_guesses: 0,
+            black_guesses: 0,
+        }
+    }
+}
+
+// How a team is doing, when playing in teams
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct TeamStats {
+    pub team: u8,
+    pub players: Vec<String>,
+    pub turns: u32,
+    pub correct: u32,
+    // Total seconds of penalties the team has run up, however they were shared out
+    pub penalty: u32,
+}
+
+// Work out the score for each team from the turn history. `teams` has the players in each team,
+// starting with team 1.
+pub fn team_stats(history: &[HistoryItem], teams: &[Vec<String>]) -> Vec<TeamStats> {
+    teams
+        .iter()
+        .enumerate()
+        .map(|(index, players)| {
+            let team = index as u8 + 1;
+            let mut stats = TeamStats {
+                team,
+                players: players.clone(),
+                turns: 0,
+                correct: 0,
+                penalty: 0,
+            };
+            for item in history.iter().filter(|item| item.team == Some(team)) {
+                stats.turns += 1;
+                if item.outcome {
+                    stats.correct += 1;
+                } else {
+                    stats.penalty += u32::from(item.penalty);
+                }
+            }
+            stats
+        })
+        .collect()
+}
+
+// Work out the stats for each player from the turn history. Players are listed in the order that
+// they first played.
+pub fn player_stats(history: &[HistoryItem]) -> Vec<PlayerStats> {
+    let mut stats: Vec<PlayerStats> = Vec::new();
+    // The current streak of correct guesses, indexed the same as stats
+    let mut streaks: Vec<u32> = Vec::new();
+
+    for item in history {
+        let index = match stats.iter().position(|s| s.username == item.username) {
+            Some(index) => index,
+            None => {
+                stats.push(PlayerStats::new(item.username.clone()));
+                streaks.push(0);
+                stats.len() - 1
+            }
+        };
+        let player = &mut stats[index];
+
+        player.turns += 1;
+        match item.guess {
+            CardColour::Red => player.red_guesses += 1,
+            CardColour::Black => player.black_guesses += 1,
+        }
+
+        if item.outcome {
+            player.correct += 1;
+            streaks[index] += 1;
+            if streaks[index] > player.longest_streak {
+                player.longest_streak = streaks[index];
+            }
+        } else {
+            streaks[index] = 0;
+            player.penalty_served += u32::from(item.penalty);
+            if item.penalty > player.biggest_penalty {
+                player.biggest_penalty = item.penalty;
+            }
+        }
+    }
+
+    for player in &mut stats {
+        player.correct_percentage = f64::from(player.correct) * 100.0 / f64::from(player.turns);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod player_stats {
+    use super::*;
+    use deck::{Card, Suit, Value};
+
+    fn item(username: &str, guess: CardColour, outcome: bool, penalty: u16) -> HistoryItem {
+        HistoryItem {
+            username: username.to_string(),
+            guess,
+            outcome,
+            card: Card {
+                value: Value::Ace,
+                suit: Suit::Club,
+            },
+            penalty,
+            turn_number: 1,
+            timestamp: 0,
+            team: None,
+        }
+    }
+
+    #[test]
+    fn empty_history() {
+        assert!(player_stats(&[]).is_empty());
+    }
+
+    #[test]
+    fn stats_are_per_player() {
+        let history = vec![
+            item("mick", CardColour::Red, true, 10),
+            item("john", CardColour::Black, true, 15),
+            item("mick", CardColour::Red, false, 15),
+            item("john", CardColour::Black, true, 10),
+            item("mick", CardColour::Black, true, 10),
+            item("john", CardColour::Red, false, 15),
+        ];
+        let stats = player_stats(&history);
+        assert_eq!(stats.len(), 2);
+
+        let mick = &stats[0];
+        assert_eq!(mick.username, "mick");
+        assert_eq!(mick.turns, 3);
+        assert_eq!(mick.correct, 2);
+        assert_eq!(mick.red_guesses, 2);
+        assert_eq!(mick.black_guesses, 1);
+        assert_eq!(mick.penalty_served, 15);
+        assert_eq!(mick.biggest_penalty, 15);
+
+        let john = &stats[1];
+        assert_eq!(john.username, "john");
+        assert_eq!(john.longest_streak, 2);
+        assert_eq!(john.red_guesses, 1);
+        assert_eq!(john.black_guesses, 2);
+    }
+
+    #[test]
+    fn team_scores() {
+        let mut history = vec![
+            item("mick", CardColour::Red, true, 10),
+            item("john", CardColour::Black, false, 10),
+            item("begbie", CardColour::Red, false, 5),
+            item("spud", CardColour::Red, true, 10),
+        ];
+        for (item, team) in history.iter_mut().zip(&[1, 2, 1, 2]) {
+            item.team = Some(*team);
+        }
+        let teams = vec![
+            vec!["mick".to_string(), "begbie".to_string()],
+            vec!["john".to_string(), "spud".to_string()],
+        ];
+        let stats = team_stats(&history, &teams);
+        assert_eq!(stats[0].team, 1);
+        assert_eq!(stats[0].players, teams[0]);
+        assert_eq!((stats[0].turns, stats[0].correct, stats[0].penalty), (2, 1, 5));
+        assert_eq!((stats[1].turns, stats[1].correct, stats[1].penalty), (2, 1, 10));
+    }
+
+    #[test]
+    fn longest_streak_and_penalties() {
+        let history = vec![
+            item("mick", CardColour::Red, true, 10),
+            item("mick", CardColour::Red, true, 15),
+            item("mick", CardColour::Red, false, 15),
+            item("mick", CardColour::Red, true, 10),
+            item("mick", CardColour::Red, false, 10),
+            item("mick", CardColour::Red, false, 5),
+        ];
+        let stats = player_stats(&history);
+        assert_eq!(stats[0].longest_streak, 2);
+        assert_eq!(stats[0].penalty_served, 30);
+        assert_eq!(stats[0].biggest_penalty, 15);
+        assert_eq!(stats[0].correct_percentage, 50.0);
+    }
+}
@@ -0,0 +1,161 @@
+use super::export::{export_history, ExportFormat};
+use super::room::Room;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// When idle rooms and connections are cleaned up
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweeperSettings {
+    // How often to look for idle rooms and connections
+    pub interval: Duration,
+    // How long a room can go without any messages before it is closed, zero to keep rooms open
+    pub room_idle: Duration,
+    // How long a connection can go without sending anything (including pings) before it is
+    // closed, zero to keep connections open
+    pub connection_idle: Duration,
+    // Where to save the history of rooms before they are closed, if anywhere
+    pub archive_dir: Option<PathBuf>,
+}
+
+impl Default for SweeperSettings {
+    fn default() -> Self {
+        SweeperSettings {
+            interval: Duration::from_secs(60),
+            room_idle: Duration::from_secs(30 * 60),
+            connection_idle: Duration::from_secs(10 * 60),
+            archive_dir: None,
+        }
+    }
+}
+
+// Shared by every connection, so only one of them sweeps each interval
+#[derive(Debug)]
+pub struct Sweeper {
+    pub settings: SweeperSettings,
+    last_sweep: Instant,
+    rooms_evicted: usize,
+    connections_closed: usize,
+}
+
+impl Sweeper {
+    pub fn new(settings: SweeperSettings) -> Self {
+        Sweeper {
+            settings,
+            last_sweep: Instant::now(),
+            rooms_evicted: 0,
+            connections_closed: 0,
+        }
+    }
+
+    // Whether it's time to look for idle rooms. Counts as a sweep if it is.
+    pub fn is_due(&mut self, now: Instant) -> bool {
+        if self.settings.room_idle == Duration::from_secs(0)
+            || now.duration_since(self.last_sweep) < self.settings.interval
+        {
+            return false;
+        }
+        self.last_sweep = now;
+        true
+    }
+
+    pub fn is_connection_idle(&self, last_activity: Instant, now: Instant) -> bool {
+        self.settings.connection_idle > Duration::from_secs(0)
+            && now.duration_since(last_activity) >= self.settings.connection_idle
+    }
+
+    // Count an evicted room, returning how many there have been
+    pub fn room_evicted(&mut self) -> usize {
+        self.rooms_evicted += 1;
+        self.rooms_evicted
+    }
+
+    // Count a closed idle connection, returning how many there have been
+    pub fn connection_closed(&mut self) -> usize {
+        self.connections_closed += 1;
+        self.connections_closed
+    }
+}
+
+// Save the history of a room's game as `<room>-<game id>.jsonl`. Nothing is saved if no turns
+// were played.
+pub fn archive_room(dir: &Path, room: &Room) -> io::Result<Option<PathBuf>> {
+    let game = room.game.borrow();
+    let history = game.get_full_history();
+    if history.is_empty() {
+        return Ok(None);
+    }
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}-{}.jsonl", room.name, game.game_id()));
+    fs::write(&path, export_history(history, ExportFormat::JsonLines))?;
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod sweeping {
+    use super::*;
+    use red_or_black::chat::ChatSettings;
+    use red_or_black::messages::CardColour;
+    use red_or_black::rules::RedOrBlack;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn sweeps_once_per_interval() {
+        let mut sweeper = Sweeper::new(SweeperSettings::default());
+        let start = sweeper.last_sweep;
+        assert!(!sweeper.is_due(start + Duration::from_secs(30)));
+        assert!(sweeper.is_due(start + Duration::from_secs(60)));
+        assert!(!sweeper.is_due(start + Duration::from_secs(61)));
+        assert!(sweeper.is_due(start + Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn zero_turns_sweeping_off() {
+        let mut sweeper = Sweeper::new(SweeperSettings {
+            room_idle: Duration::from_secs(0),
+            connection_idle: Duration::from_secs(0),
+            ..SweeperSettings::default()
+        });
+        let later = sweeper.last_sweep + Duration::from_secs(3600);
+        assert!(!sweeper.is_due(later));
+        assert!(!sweeper.is_connection_idle(sweeper.last_sweep, later));
+    }
+
+    #[test]
+    fn idle_connections() {
+        let sweeper = Sweeper::new(SweeperSettings::default());
+        let now = Instant::now();
+        assert!(!sweeper.is_connection_idle(now, now + Duration::from_secs(599)));
+        assert!(sweeper.is_connection_idle(now, now + Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn counts_evictions() {
+        let mut sweeper = Sweeper::new(SweeperSettings::default());
+        assert_eq!(sweeper.room_evicted(), 1);
+        assert_eq!(sweeper.room_evicted(), 2);
+        assert_eq!(sweeper.connection_closed(), 1);
+    }
+
+    #[test]
+    fn archives_played_games() {
+        let dir = env::temp_dir().join(format!("red_or_black_archive_{}", process::id()));
+        let game = RedOrBlack::new(vec!["amy".to_string()]);
+        let room = Room::new("quiet", game, ChatSettings::default());
+        assert_eq!(archive_room(&dir, &room).unwrap(), None);
+
+        room.game.borrow_mut().play_turn(&CardColour::Red);
+        let path = archive_room(&dir, &room).unwrap().unwrap();
+        let archived = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("quiet-"));
+        assert_eq!(archived.lines().count(), 1);
+    }
+}
@@ -0,0 +1,486 @@
+use super::history::HistoryItem;
+use super::messages::{CardColour, PlayerInfo, ReceivableMessage, SendableMessage};
+use super::stats::TeamStats;
+use deck::Card;
+
+// How many turns of the game history to show when joining
+const RECENT_TURNS: usize = 5;
+
+pub const HELP: &str = "Type r or b to guess red or black, h for the history, p for the players \
+                        and q to quit. Anything else is sent to the chat.";
+
+// What to do with a line typed into the terminal client
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Send(ReceivableMessage),
+    // JSON typed in raw mode, sent as it is
+    SendRaw(String),
+    ShowHistory,
+    ShowPlayers,
+    Help,
+    Quit,
+    Nothing,
+}
+
+fn guess(card_colour: CardColour) -> ReceivableMessage {
+    ReceivableMessage::Guess {
+        card_colour,
+        guess_id: None,
+        turn_number: None,
+    }
+}
+
+pub fn parse_input(line: &str, raw: bool) -> Input {
+    let line = line.trim();
+    match line.to_lowercase().as_str() {
+        "" => Input::Nothing,
+        "r" => Input::Send(guess(CardColour::Red)),
+        "b" => Input::Send(guess(CardColour::Black)),
+        "h" => Input::ShowHistory,
+        "p" => Input::ShowPlayers,
+        "?" | "help" => Input::Help,
+        "q" => Input::Quit,
+        _ if raw && line.starts_with('{') => Input::SendRaw(line.to_string()),
+        _ => Input::Send(ReceivableMessage::Chat {
+            text: line.to_string(),
+        }),
+    }
+}
+
+fn card_name(card: &Card) -> String {
+    format!("{:?} of {:?}s", card.value, card.suit)
+}
+
+fn history_line(item: &HistoryItem) -> String {
+    format!(
+        "  {:>3}. {} guessed {:?}, {}: {}",
+        item.turn_number,
+        item.username,
+        item.guess,
+        card_name(&item.card),
+        if item.outcome {
+            "right".to_string()
+        } else {
+            format!("wrong, drank for {} seconds", item.penalty)
+        }
+    )
+}
+
+fn player_name(player: &PlayerInfo) -> String {
+    let name = match (player.bot, player.latency_ms) {
+        (true, _) => format!("{} [bot]", player.username),
+        (false, Some(latency)) => format!("{} ({}ms)", player.username, latency),
+        (false, None) => player.username.clone(),
+    };
+    match player.team {
+        Some(team) => format!("{} [team {}]", name, team),
+        None => name,
+    }
+}
+
+fn team_line(team: &TeamStats) -> String {
+    format!(
+        "  Team {} ({}): {} of {} right, {} seconds of penalties",
+        team.team,
+        team.players.join(", "),
+        team.correct,
+        team.turns,
+        team.penalty
+    )
+}
+
+// Keeps track of the game so it can be shown as lines of text
+pub struct TerminalView {
+    username: String,
+    players: Vec<String>,
+    history: Vec<HistoryItem>,
+}
+
+impl TerminalView {
+    pub fn new(username: &str) -> Self {
+        TerminalView {
+            username: username.to_string(),
+            players: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    // The lines to print for a message from the server
+    pub fn render(&mut self, message: SendableMessage) -> Vec<String> {
+        match message {
+            SendableMessage::LoggedIn => vec![format!("Logged in as {}. {}", self.username, HELP)],
+            SendableMessage::Players { players, details } => {
+                self.players = if details.is_empty() {
+                    players
+                } else {
+                    details.iter().map(player_name).collect()
+                };
+                self.players_lines()
+            }
+            SendableMessage::Turn { ref username, .. } if *username == self.username => {
+                vec!["It's your turn! Red (r) or black (b)?".to_string()]
+            }
+            SendableMessage::Turn {
+                username,
+                team: Some(team),
+                ..
+            } => vec![format!("It's {}'s turn (team {})", username, team)],
+            SendableMessage::Turn { username, .. } => vec![format!("It's {}'s turn", username)],
+            SendableMessage::GuessResult {
+                correct,
+                card,
+                penalty,
+                username,
+                guess,
+                shares,
+                ..
+            } => {
+                let result = if correct {
+                    format!("right! The penalty is now {} seconds", penalty)
+                } else if !shares.is_empty() {
+                    let drinkers: Vec<String> = shares
+                        .iter()
+                        .map(|s| format!("{} {}s", s.username, s.seconds))
+                        .collect();
+                    format!("wrong, drink up: {}", drinkers.join(", "))
+                } else {
+                    format!("wrong, drink for {} seconds!", penalty)
+                };
+                vec![format!(
+                    "{} guessed {:?}, the card was the {}: {}",
+                    username,
+                    guess,
+                    card_name(&card),
+                    result
+                )]
+            }
+            SendableMessage::RoundStarted { waiting_for, .. } => {
+                if waiting_for.contains(&self.username) {
+                    vec!["Everyone's guessing the next card! Red (r) or black (b)?".to_string()]
+                } else {
+                    vec!["Everyone's guessing the next card".to_string()]
+                }
+            }
+            SendableMessage::GuessReceived {
+                username,
+                waiting_for,
+                closes_in_ms,
+                ..
+            } => {
+                if waiting_for.is_empty() {
+                    return vec![format!("{} has guessed", username)];
+                }
+                vec![format!(
+                    "{} has guessed, waiting {}s for {}",
+                    username,
+                    closes_in_ms.div_ceil(1000),
+                    waiting_for.join(", ")
+                )]
+            }
+            SendableMessage::RoundResult {
+                card,
+                penalty,
+                everyone_right,
+                guesses,
+                shares,
+                ..
+            } => {
+                let mut lines = vec![format!("The card was the {}", card_name(&card))];
+                lines.extend(guesses.iter().map(|item| {
+                    if item.outcome {
+                        format!("  {} guessed {:?}: right", item.username, item.guess)
+                    } else if !shares.is_empty() {
+                        format!("  {} guessed {:?}: wrong", item.username, item.guess)
+                    } else {
+                        format!(
+                            "  {} guessed {:?}: wrong, drink for {} seconds!",
+                            item.username, item.guess, penalty
+                        )
+                    }
+                }));
+                if !shares.is_empty() {
+                    let drinkers: Vec<String> = shares
+                        .iter()
+                        .map(|s| format!("{} {}s", s.username, s.seconds))
+                        .collect();
+                    lines.push(format!("Drink up: {}", drinkers.join(", ")));
+                }
+                if everyone_right {
+                    lines.push("Everyone was right, the penalty goes up!".to_string());
+                }
+                lines
+            }
+            SendableMessage::Penalty { penalty } => {
+                vec![format!("The penalty is {} seconds", penalty)]
+            }
+            // Pages go back in time, so they come before the turns already known
+            SendableMessage::HistoryPage { items, .. } => {
+                let first = self.history.first().map(|item| item.turn_number);
+                let mut older: Vec<HistoryItem> = items
+                    .into_iter()
+                    .filter(|item| first.is_none_or(|turn| item.turn_number < turn))
+                    .collect();
+                if older.is_empty() {
+                    return Vec::new();
+                }
+                let mut lines = vec!["Recent turns:".to_string()];
+                let skip = older.len().saturating_sub(RECENT_TURNS);
+                lines.extend(older.iter().skip(skip).map(history_line));
+                older.append(&mut self.history);
+                self.history = older;
+                lines
+            }
+            SendableMessage::HistoryAppended(item) => {
+                self.history.push(item);
+                Vec::new()
+            }
+            SendableMessage::CardsLeft { cards_left } => {
+                vec![format!("{} cards left in the deck", cards_left)]
+            }
+            SendableMessage::DeckExhausted(exhausted) => vec![format!(
+                "The deck ran out ({:?}), {} cards in the new deck",
+                exhausted.reason, exhausted.deck_size
+            )],
+            SendableMessage::PlayerHasLeft { username } => {
+                vec![format!("A player left, it's {}'s turn", username)]
+            }
+            SendableMessage::GameOver {
+                turns_played,
+                players,
+                teams,
+                ..
+            } => {
+                let mut lines = vec![format!("Game over after {} turns", turns_played)];
+                lines.extend(players.iter().map(|p| {
+                    format!(
+                        "  {}: {} of {} right, drank for {} seconds",
+                        p.username, p.correct, p.turns, p.penalty_served
+                    )
+                }));
+                lines.extend(teams.iter().map(team_line));
+                lines
+            }
+            SendableMessage::TeamScores { teams } => {
+                let mut lines = vec!["Team scores:".to_string()];
+                lines.extend(teams.iter().map(team_line));
+                lines
+            }
+            SendableMessage::Chat(chat) => vec![format!("<{}> {}", chat.username, chat.text)],
+            SendableMessage::Error { error } => vec![format!("Error: {}", error)],
+            SendableMessage::Nack { error, .. } => vec![format!("Error: {}", error)],
+            SendableMessage::LimitExceeded { error, .. } => vec![format!("Error: {}", error)],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn players_lines(&self) -> Vec<String> {
+        vec![format!("Players: {}", self.players.join(", "))]
+    }
+
+    pub fn history_lines(&self) -> Vec<String> {
+        if self.history.is_empty() {
+            return vec!["No turns played yet".to_string()];
+        }
+        self.history.iter().map(history_line).collect()
+    }
+}
+
+#[cfg(test)]
+mod rendering {
+    use super::*;
+    use deck::{Suit, Value};
+    use red_or_black::rules::PenaltyShare;
+
+    fn item(turn_number: u16, outcome: bool) -> HistoryItem {
+        HistoryItem {
+            username: "amy".to_string(),
+            guess: CardColour::Red,
+            outcome,
+            card: Card {
+                value: Value::Queen,
+                suit: Suit::Heart,
+            },
+            penalty: 10,
+            turn_number,
+            timestamp: 0,
+            team: None,
+        }
+    }
+
+    #[test]
+    fn inputs() {
+        assert_eq!(
+            parse_input(" R ", false),
+            Input::Send(guess(CardColour::Red))
+        );
+        assert_eq!(parse_input("q", false), Input::Quit);
+        assert_eq!(parse_input("", false), Input::Nothing);
+        assert_eq!(
+            parse_input("{\"ListRooms\": null}", true),
+            Input::SendRaw("{\"ListRooms\": null}".to_string())
+        );
+        assert_eq!(
+            parse_input("{hi}", false),
+            Input::Send(ReceivableMessage::Chat {
+                text: "{hi}".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn turns_and_guesses() {
+        let mut view = TerminalView::new("amy");
+        assert_eq!(
+            view.render(SendableMessage::Turn {
+                username: "amy".to_string(),
+                turn_number: 1,
+                team: None,
+            }),
+            vec!["It's your turn! Red (r) or black (b)?"]
+        );
+        assert_eq!(
+            view.render(SendableMessage::GuessResult {
+                correct: false,
+                card: item(1, false).card,
+                penalty: 15,
+                username: "bob".to_string(),
+                guess: CardColour::Black,
+                team: None,
+                shares: Vec::new(),
+            }),
+            vec![
+                "bob guessed Black, the card was the Queen of Hearts: wrong, drink for 15 seconds!"
+            ]
+        );
+    }
+
+    #[test]
+    fn teams() {
+        let mut view = TerminalView::new("amy");
+        assert_eq!(
+            view.render(SendableMessage::Turn {
+                username: "bob".to_string(),
+                turn_number: 2,
+                team: Some(2),
+            }),
+            vec!["It's bob's turn (team 2)"]
+        );
+        let share = |username: &str| PenaltyShare {
+            username: username.to_string(),
+            seconds: 8,
+        };
+        assert_eq!(
+            view.render(SendableMessage::GuessResult {
+                correct: false,
+                card: item(2, false).card,
+                penalty: 15,
+                username: "bob".to_string(),
+                guess: CardColour::Black,
+                team: Some(2),
+                shares: vec![share("bob"), share("cat")],
+            }),
+            vec![
+                "bob guessed Black, the card was the Queen of Hearts: wrong, drink up: bob 8s, cat 8s"
+            ]
+        );
+        let lines = view.render(SendableMessage::TeamScores {
+            teams: vec![TeamStats {
+                team: 2,
+                players: vec!["bob".to_string(), "cat".to_string()],
+                turns: 3,
+                correct: 1,
+                penalty: 25,
+            }],
+        });
+        assert_eq!(
+            lines,
+            vec![
+                "Team scores:",
+                "  Team 2 (bob, cat): 1 of 3 right, 25 seconds of penalties",
+            ]
+        );
+    }
+
+    #[test]
+    fn everyone_plays() {
+        let mut view = TerminalView::new("amy");
+        assert_eq!(
+            view.render(SendableMessage::GuessReceived {
+                username: "bob".to_string(),
+                turn_number: 1,
+                waiting_for: vec!["amy".to_string(), "cat".to_string()],
+                closes_in_ms: 14_500,
+            }),
+            vec!["bob has guessed, waiting 15s for amy, cat"]
+        );
+        let amy = item(1, true);
+        let mut bob = item(1, false);
+        bob.username = "bob".to_string();
+        bob.guess = CardColour::Black;
+        assert_eq!(
+            view.render(SendableMessage::RoundResult {
+                turn_number: 1,
+                card: bob.card,
+                penalty: 10,
+                everyone_right: false,
+                guesses: vec![amy, bob],
+                shares: Vec::new(),
+            }),
+            vec![
+                "The card was the Queen of Hearts",
+                "  amy guessed Red: right",
+                "  bob guessed Black: wrong, drink for 10 seconds!",
+            ]
+        );
+    }
+
+    #[test]
+    fn players() {
+        let mut view = TerminalView::new("amy");
+        let lines = view.render(SendableMessage::Players {
+            players: vec!["amy".to_string(), "Red Bot".to_string()],
+            details: vec![
+                PlayerInfo {
+                    username: "amy".to_string(),
+                    latency_ms: Some(42),
+                    bot: false,
+                    team: None,
+                },
+                PlayerInfo {
+                    username: "Red Bot".to_string(),
+                    latency_ms: None,
+                    bot: true,
+                    team: Some(2),
+                },
+            ],
+        });
+        assert_eq!(lines, vec!["Players: amy (42ms), Red Bot [bot] [team 2]"]);
+    }
+
+    #[test]
+    fn history() {
+        let mut view = TerminalView::new("amy");
+        assert_eq!(view.history_lines(), vec!["No turns played yet"]);
+        let items = (3..8).map(|n| item(n, n % 2 == 0)).collect();
+        let lines = view.render(SendableMessage::HistoryPage {
+            items,
+            has_more: true,
+        });
+        assert_eq!(lines.len(), RECENT_TURNS + 1);
+        assert_eq!(
+            lines[1],
+            "    3. amy guessed Red, Queen of Hearts: wrong, drank for 10 seconds"
+        );
+        assert!(view
+            .render(SendableMessage::HistoryAppended(item(8, true)))
+            .is_empty());
+        // An older page goes before what's already there
+        let items = (1..4).map(|n| item(n, true)).collect();
+        view.render(SendableMessage::HistoryPage {
+            items,
+            has_more: false,
+        });
+        let turns: Vec<u16> = view.history.iter().map(|i| i.turn_number).collect();
+        assert_eq!(turns, (1..9).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,18 @@
+// Tokens for the server's own timeouts. They count down from the top so they never clash with the
+// replay timeouts, which use the tokens counting up from zero.
+use ws::util::Token;
+
+// Sweeps idle rooms and old game logs
+pub const SWEEP_TIMEOUT: Token = Token(usize::MAX);
+
+// Pings clients and drops the ones that stopped answering
+pub const PING_TIMEOUT: Token = Token(usize::MAX - 1);
+
+// Makes the bot whose turn it is guess
+pub const BOT_TIMEOUT: Token = Token(usize::MAX - 2);
+
+// Reveals the card once the guess window is up in everyone plays mode
+pub const ROUND_TIMEOUT: Token = Token(usize::MAX - 3);
+
+// Ends games with a time limit when the time is up
+pub const GAME_TIMEOUT: Token = Token(usize::MAX - 4);
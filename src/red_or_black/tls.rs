@@ -0,0 +1,104 @@
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use std::path::Path;
+
+// Build the acceptor used to encrypt connections from a PEM certificate (which can include the
+// rest of the chain) and a PEM private key
+pub fn acceptor(cert_path: &Path, key_path: &Path) -> Result<SslAcceptor, ErrorStack> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_certificate_chain_file(cert_path)?;
+    builder.set_private_key_file(key_path, SslFiletype::PEM)?;
+    builder.check_private_key()?;
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod acceptor {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslConnector, SslVerifyMode};
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::process;
+    use std::thread;
+
+    fn key() -> PKey<Private> {
+        PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap()
+    }
+
+    fn self_signed(key: &PKey<Private>) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(key, MessageDigest::sha256()).unwrap();
+        cert.build()
+    }
+
+    // Write a certificate and key to a temporary directory, returning their paths
+    fn write_pem(name: &str, cert: &X509, key: &PKey<Private>) -> (PathBuf, PathBuf) {
+        let dir = env::temp_dir().join(format!("red_or_black_tls_{}_{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+        fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[test]
+    fn encrypts_connections() {
+        let key = key();
+        let (cert_path, key_path) = write_pem("valid", &self_signed(&key), &key);
+        let acceptor = acceptor(&cert_path, &key_path).unwrap();
+        fs::remove_dir_all(cert_path.parent().unwrap()).unwrap();
+
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut stream = acceptor.accept(server).unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).unwrap();
+            buf
+        });
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        // The certificate is self signed, so there's nothing to verify it against
+        connector.set_verify(SslVerifyMode::NONE);
+        let mut stream = connector.build().connect("localhost", client).unwrap();
+        stream.write_all(b"hello").unwrap();
+        assert_eq!(&server.join().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn key_must_match_certificate() {
+        let (cert_path, key_path) = write_pem("mismatched", &self_signed(&key()), &key());
+        let result = acceptor(&cert_path, &key_path);
+        fs::remove_dir_all(cert_path.parent().unwrap()).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn missing_files() {
+        let missing = Path::new("/nonexistent/red_or_black.pem");
+        assert!(acceptor(missing, missing).is_err());
+    }
+}